@@ -0,0 +1,495 @@
+use crate::utils::{ArithmeticError, ExecutionContext};
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithmeticToken {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+#[derive(Debug)]
+enum Expression {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// `name = value`, or `name op= value` when `operator` is set.
+    Assign {
+        name: String,
+        operator: Option<&'static str>,
+        value: Box<Expression>,
+    },
+}
+
+// Longer operators come first so that the tokenizer prefers them.
+const OPERATORS: [&str; 33] = [
+    "<<=", ">>=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=",
+    "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+/// Binary operators from the loosest to the tightest binding.
+const BINARY_PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Evaluates the text of a `$((...))` expansion with 64-bit signed integers,
+/// reading and assigning shell variables through `context`.
+pub fn evaluate(expression: &str, context: &mut ExecutionContext) -> Result<i64, ArithmeticError> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ArithmeticParser {
+        tokens,
+        position: 0,
+    };
+    let expression = parser.parse_assignment()?;
+    if let Some(token) = parser.peek() {
+        return Err(ArithmeticError::UnexpectedToken(token_text(token)));
+    }
+
+    eval(&expression, context)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<ArithmeticToken>, ArithmeticError> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_digit() {
+            let end = rest
+                .find(|ch: char| !ch.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(ArithmeticToken::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let end = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(ArithmeticToken::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if ch == '(' || ch == ')' {
+            tokens.push(match ch {
+                '(' => ArithmeticToken::LeftParen,
+                _ => ArithmeticToken::RightParen,
+            });
+            rest = &rest[1..];
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .ok_or_else(|| ArithmeticError::UnexpectedToken(ch.to_string()))?;
+            tokens.push(ArithmeticToken::Operator(operator));
+            rest = &rest[operator.len()..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a decimal, octal (`0` prefix) or hexadecimal (`0x` prefix) integer
+/// constant.
+fn parse_number(text: &str) -> Result<i64, ArithmeticError> {
+    let invalid = || ArithmeticError::InvalidNumber(text.to_string());
+
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if text.len() > 1 && text.starts_with('0') {
+            (&text[1..], 8)
+        } else {
+            (text, 10)
+        };
+
+    if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+        return Err(invalid());
+    }
+    i64::from_str_radix(digits, radix).map_err(|_| ArithmeticError::Overflow)
+}
+
+fn token_text(token: &ArithmeticToken) -> String {
+    match token {
+        ArithmeticToken::Number(number) => number.to_string(),
+        ArithmeticToken::Name(name) => name.clone(),
+        ArithmeticToken::Operator(operator) => operator.to_string(),
+        ArithmeticToken::LeftParen => "(".to_string(),
+        ArithmeticToken::RightParen => ")".to_string(),
+    }
+}
+
+struct ArithmeticParser {
+    tokens: Vec<ArithmeticToken>,
+    position: usize,
+}
+
+impl ArithmeticParser {
+    fn peek(&self) -> Option<&ArithmeticToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<ArithmeticToken> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(ArithmeticToken::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect_operator(&mut self, expected: &str) -> Result<(), ArithmeticError> {
+        match self.advance() {
+            Some(ArithmeticToken::Operator(operator)) if operator == expected => Ok(()),
+            Some(token) => Err(ArithmeticError::UnexpectedToken(token_text(&token))),
+            None => Err(ArithmeticError::UnexpectedEnd),
+        }
+    }
+
+    /// assignment := NAME assign-op assignment | conditional
+    fn parse_assignment(&mut self) -> Result<Expression, ArithmeticError> {
+        if let Some(ArithmeticToken::Name(name)) = self.peek().cloned()
+            && let Some(ArithmeticToken::Operator(operator)) = self.tokens.get(self.position + 1)
+            && ASSIGNMENT_OPERATORS.contains(operator)
+        {
+            let operator = *operator;
+            self.position += 2;
+            let value = self.parse_assignment()?;
+            return Ok(Expression::Assign {
+                name,
+                operator: operator.strip_suffix('=').filter(|op| !op.is_empty()),
+                value: Box::new(value),
+            });
+        }
+
+        self.parse_conditional()
+    }
+
+    /// conditional := binary ('?' assignment ':' conditional)?
+    fn parse_conditional(&mut self) -> Result<Expression, ArithmeticError> {
+        let condition = self.parse_binary(0)?;
+
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.advance();
+        let then_branch = self.parse_assignment()?;
+        self.expect_operator(":")?;
+        let else_branch = self.parse_conditional()?;
+
+        Ok(Expression::Conditional(
+            Box::new(condition),
+            Box::new(then_branch),
+            Box::new(else_branch),
+        ))
+    }
+
+    /// Parses left-associative binary operators from `level` upwards.
+    fn parse_binary(&mut self, level: usize) -> Result<Expression, ArithmeticError> {
+        if level == BINARY_PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(operator) = self.peek_operator()
+            && BINARY_PRECEDENCE[level].contains(&operator)
+        {
+            self.advance();
+            let right = self.parse_binary(level + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    /// unary := ('+' | '-' | '!' | '~') unary | primary
+    fn parse_unary(&mut self) -> Result<Expression, ArithmeticError> {
+        match self.peek_operator() {
+            Some(operator @ ("+" | "-" | "!" | "~")) => {
+                self.advance();
+                Ok(Expression::Unary(operator, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    /// primary := NUMBER | NAME | '(' assignment ')'
+    fn parse_primary(&mut self) -> Result<Expression, ArithmeticError> {
+        match self.advance() {
+            Some(ArithmeticToken::Number(number)) => Ok(Expression::Number(number)),
+            Some(ArithmeticToken::Name(name)) => Ok(Expression::Variable(name)),
+            Some(ArithmeticToken::LeftParen) => {
+                let expression = self.parse_assignment()?;
+                match self.advance() {
+                    Some(ArithmeticToken::RightParen) => Ok(expression),
+                    Some(token) => Err(ArithmeticError::UnexpectedToken(token_text(&token))),
+                    None => Err(ArithmeticError::UnexpectedEnd),
+                }
+            }
+            Some(token) => Err(ArithmeticError::UnexpectedToken(token_text(&token))),
+            None => Err(ArithmeticError::UnexpectedEnd),
+        }
+    }
+}
+
+fn eval(expression: &Expression, context: &mut ExecutionContext) -> Result<i64, ArithmeticError> {
+    match expression {
+        Expression::Number(number) => Ok(*number),
+        Expression::Variable(name) => variable_value(name, context),
+        Expression::Unary(operator, operand) => {
+            let value = eval(operand, context)?;
+            match *operator {
+                "+" => Ok(value),
+                "-" => value.checked_neg().ok_or(ArithmeticError::Overflow),
+                "!" => Ok((value == 0) as i64),
+                "~" => Ok(!value),
+                _ => unreachable!(),
+            }
+        }
+        Expression::Binary("&&", left, right) => {
+            Ok((eval(left, context)? != 0 && eval(right, context)? != 0) as i64)
+        }
+        Expression::Binary("||", left, right) => {
+            Ok((eval(left, context)? != 0 || eval(right, context)? != 0) as i64)
+        }
+        Expression::Binary(operator, left, right) => {
+            let left = eval(left, context)?;
+            let right = eval(right, context)?;
+            apply_binary(operator, left, right)
+        }
+        Expression::Conditional(condition, then_branch, else_branch) => {
+            if eval(condition, context)? != 0 {
+                eval(then_branch, context)
+            } else {
+                eval(else_branch, context)
+            }
+        }
+        Expression::Assign {
+            name,
+            operator,
+            value,
+        } => {
+            let value = eval(value, context)?;
+            let value = match operator {
+                Some(operator) => apply_binary(operator, variable_value(name, context)?, value)?,
+                None => value,
+            };
+            context
+                .set_variable(name, value.to_string())
+                .map_err(|_| ArithmeticError::ReadonlyVariable(name.clone()))?;
+            Ok(value)
+        }
+    }
+}
+
+fn apply_binary(operator: &str, left: i64, right: i64) -> Result<i64, ArithmeticError> {
+    let shift = |right: i64| u32::try_from(right).map_err(|_| ArithmeticError::Overflow);
+
+    let result = match operator {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Err(ArithmeticError::DivisionByZero),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        "<<" => left.checked_shl(shift(right)?),
+        ">>" => left.checked_shr(shift(right)?),
+        "<" => Some((left < right) as i64),
+        "<=" => Some((left <= right) as i64),
+        ">" => Some((left > right) as i64),
+        ">=" => Some((left >= right) as i64),
+        "==" => Some((left == right) as i64),
+        "!=" => Some((left != right) as i64),
+        "&" => Some(left & right),
+        "^" => Some(left ^ right),
+        "|" => Some(left | right),
+        _ => unreachable!(),
+    };
+
+    result.ok_or(ArithmeticError::Overflow)
+}
+
+/// The value of a variable used in an expression; unset and empty variables
+/// count as zero.
+fn variable_value(name: &str, context: &ExecutionContext) -> Result<i64, ArithmeticError> {
+    match context.variable(name) {
+        Some(value) if !value.trim().is_empty() => {
+            let value = value.trim();
+            match value.strip_prefix('-') {
+                Some(digits) => parse_number(digits)?
+                    .checked_neg()
+                    .ok_or(ArithmeticError::Overflow),
+                None => parse_number(value.strip_prefix('+').unwrap_or(value)),
+            }
+        }
+        _ => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn context() -> ExecutionContext {
+        ExecutionContext {
+            variables: HashMap::new(),
+            jobs: vec![],
+            shell_pgid: 0,
+            shell_pid: 0,
+            last_exit_status: 0,
+            last_background_pid: None,
+            shell_name: "flash".to_string(),
+            positional_parameters: vec![],
+            last_substitution_status: None,
+            interactive: false,
+            noclobber: false,
+            loop_depth: 0,
+            loop_control: None,
+            source: String::new(),
+        }
+    }
+
+    fn eval_str(expression: &str) -> Result<i64, ArithmeticError> {
+        evaluate(expression, &mut context())
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(eval_str("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval_str("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval_str("2 - 3 - 4").unwrap(), -5);
+        assert_eq!(eval_str("16 / 4 / 2").unwrap(), 2);
+        assert_eq!(eval_str("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(eval_str("1 | 2 ^ 3 & 1").unwrap(), 3);
+        assert_eq!(eval_str("1 < 2 == 1").unwrap(), 1);
+        assert_eq!(eval_str("0 || 2 && 3").unwrap(), 1);
+        assert_eq!(eval_str("-2 * -3").unwrap(), 6);
+        assert_eq!(eval_str("!0 + ~0").unwrap(), 0);
+        assert_eq!(eval_str("7 % 3 * 2").unwrap(), 2);
+    }
+
+    #[test]
+    fn numeric_constants() {
+        assert_eq!(eval_str("010").unwrap(), 8);
+        assert_eq!(eval_str("0x1f + 0X10").unwrap(), 47);
+        assert_eq!(eval_str("").unwrap(), 0);
+        assert!(matches!(
+            eval_str("08"),
+            Err(ArithmeticError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            eval_str("1 +"),
+            Err(ArithmeticError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            eval_str("1 2"),
+            Err(ArithmeticError::UnexpectedToken(_))
+        ));
+    }
+
+    #[test]
+    fn ternary() {
+        assert_eq!(eval_str("1 ? 2 : 3").unwrap(), 2);
+        assert_eq!(eval_str("0 ? 2 : 3").unwrap(), 3);
+        assert_eq!(eval_str("0 ? 2 : 0 ? 4 : 5").unwrap(), 5);
+        assert_eq!(eval_str("1 + 1 ? 10 : 20").unwrap(), 10);
+
+        // Only the chosen branch is evaluated.
+        let mut context = context();
+        assert_eq!(evaluate("1 ? x = 1 : (x = 2)", &mut context).unwrap(), 1);
+        assert_eq!(context.variable("x").as_deref(), Some("1"));
+        assert!(matches!(
+            eval_str("1 ? 2"),
+            Err(ArithmeticError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn assignment_operators() {
+        let mut context = context();
+        assert_eq!(evaluate("x = 5", &mut context).unwrap(), 5);
+        assert_eq!(evaluate("x += 3", &mut context).unwrap(), 8);
+        assert_eq!(evaluate("x <<= 2", &mut context).unwrap(), 32);
+        assert_eq!(evaluate("x %= 5", &mut context).unwrap(), 2);
+        assert_eq!(evaluate("y = x *= 3", &mut context).unwrap(), 6);
+        assert_eq!(context.variable("x").as_deref(), Some("6"));
+        assert_eq!(context.variable("y").as_deref(), Some("6"));
+
+        context.set_variable("z", " 12 ".to_string()).unwrap();
+        assert_eq!(evaluate("z + unset", &mut context).unwrap(), 12);
+
+        context.set_variable("r", "1".to_string()).unwrap();
+        context.variables.get_mut("r").unwrap().readonly = true;
+        assert!(matches!(
+            evaluate("r = 2", &mut context),
+            Err(ArithmeticError::ReadonlyVariable(_))
+        ));
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(matches!(
+            eval_str("9223372036854775807 + 1"),
+            Err(ArithmeticError::Overflow)
+        ));
+        assert!(matches!(
+            eval_str("9223372036854775808"),
+            Err(ArithmeticError::Overflow)
+        ));
+        assert!(matches!(
+            eval_str("-9223372036854775807 - 2"),
+            Err(ArithmeticError::Overflow)
+        ));
+        assert!(matches!(
+            eval_str("1 << 64"),
+            Err(ArithmeticError::Overflow)
+        ));
+        assert!(matches!(
+            eval_str("1 << -1"),
+            Err(ArithmeticError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(matches!(
+            eval_str("1 / 0"),
+            Err(ArithmeticError::DivisionByZero)
+        ));
+        assert!(matches!(
+            eval_str("1 % 0"),
+            Err(ArithmeticError::DivisionByZero)
+        ));
+        let mut context = context();
+        context.set_variable("x", "1".to_string()).unwrap();
+        assert!(matches!(
+            evaluate("x /= 0", &mut context),
+            Err(ArithmeticError::DivisionByZero)
+        ));
+        assert_eq!(context.variable("x").as_deref(), Some("1"));
+        // The unevaluated side of `&&` cannot fail.
+        assert_eq!(eval_str("0 && 1 / 0").unwrap(), 0);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::utils::Span;
+
+/// Formats `message` followed by the line of `source` that `span` starts on,
+/// with the spanned text underlined as `^~~~`. Spans running past the end of
+/// the line are cut off there.
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let Some(line) = source.lines().nth(span.line.saturating_sub(1)) else {
+        return format!("flash: {message}");
+    };
+
+    let column = span.column.max(1);
+    let remaining = line.chars().count().saturating_sub(column - 1);
+    let width = source
+        .get(span.start..span.end)
+        .map_or(0, |text| text.chars().take_while(|&ch| ch != '\n').count())
+        .min(remaining)
+        .max(1);
+
+    // Tabs are kept so that the caret lines up however they are displayed.
+    let indent: String = line
+        .chars()
+        .take(column - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!(
+        "flash: {}:{}: {message}\n  {line}\n  {indent}^{}",
+        span.line,
+        column,
+        "~".repeat(width - 1),
+    )
+}
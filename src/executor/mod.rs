@@ -1,77 +1,32 @@
-use std::{collections::HashMap, ffi::CString, fs::File, os::fd::AsRawFd};
+use std::{
+    env,
+    ffi::CString,
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{FromRawFd, IntoRawFd},
+        unix::ffi::OsStringExt,
+    },
+};
 
 use libc::{STDIN_FILENO, STDOUT_FILENO};
 
-use crate::utils::{
-    Command, ConditionalType, ExecutionContext, ExecutionError, RedirectionType, SimpleCommand,
+use crate::{
+    expansion, pattern,
+    utils::{
+        self, Assignment, BuiltIns, CaseItem, CaseTerminator, Command, ConditionalType,
+        ExecutionContext, ExecutionError, LoopControl, RedirectionType, SimpleCommand, Variable,
+        Word,
+    },
 };
 
 pub fn execute(
     node: &Command,
     context: &mut ExecutionContext,
-    built_ins: &HashMap<
-        String,
-        Box<dyn Fn(SimpleCommand, &mut ExecutionContext) -> Result<i32, ExecutionError>>,
-    >,
+    built_ins: &BuiltIns,
 ) -> Result<i32, ExecutionError> {
     match node {
-        Command::Simple(sc) => {
-            if let Some(closure) = built_ins.get(sc.command.as_str()) {
-                return closure(sc.clone(), context);
-            }
-            unsafe {
-                let pid = libc::fork();
-                if pid == -1 {
-                    return Err(ExecutionError::ForkFailed);
-                } else if pid == 0 {
-                    let mut action: libc::sigaction = std::mem::zeroed();
-
-                    action.sa_sigaction = libc::SIG_DFL;
-
-                    let signals_to_reset = [
-                        libc::SIGINT,
-                        libc::SIGQUIT,
-                        libc::SIGTSTP,
-                        libc::SIGTTIN,
-                        libc::SIGTTOU,
-                    ];
-
-                    for &signal in &signals_to_reset {
-                        if libc::sigaction(signal, &action, std::ptr::null_mut()) == -1 {
-                            panic!(
-                                "Failed to reset signal handler in child for signal {}",
-                                signal
-                            );
-                        }
-                    }
-
-                    let c_args = sc
-                        .arguments
-                        .iter()
-                        .map(|arg| CString::new(arg.clone()).unwrap())
-                        .collect::<Vec<CString>>();
-
-                    let mut argv = c_args
-                        .iter()
-                        .map(|arg| arg.as_ptr())
-                        .collect::<Vec<*const libc::c_char>>();
-
-                    argv.push(std::ptr::null());
-
-                    libc::execvp(argv[0], argv.as_ptr());
-
-                    libc::exit(1);
-                } else {
-                    // libc::tcsetpgrp(libc::STDIN_FILENO, pid);
-
-                    let mut status = 0;
-                    libc::waitpid(pid, &mut status, 0);
-
-                    // libc::tcsetpgrp(libc::STDIN_FILENO, context.shell_pgid as i32);
-                    return Ok(status);
-                }
-            }
-        }
+        Command::Simple(sc) => execute_simple(sc, context, built_ins).or_else(recover),
         Command::Pipe { left, right } => unsafe {
             let mut pipe_fd = [0; 2];
             libc::pipe(pipe_fd.as_mut_ptr());
@@ -85,7 +40,7 @@ pub fn execute(
                 libc::dup2(pipe_fd[1], STDOUT_FILENO);
                 libc::close(pipe_fd[1]);
 
-                let exit_code = execute(left, context, built_ins).unwrap_or(1);
+                let exit_code = child_status(execute(left, context, built_ins));
 
                 libc::exit(exit_code);
             }
@@ -99,7 +54,7 @@ pub fn execute(
                 libc::dup2(pipe_fd[0], STDIN_FILENO);
                 libc::close(pipe_fd[0]);
 
-                let exit_code = execute(right, context, built_ins).unwrap_or(1);
+                let exit_code = child_status(execute(right, context, built_ins));
 
                 libc::exit(exit_code);
             }
@@ -111,113 +66,635 @@ pub fn execute(
             libc::waitpid(pid_left, &mut status, 0);
             libc::waitpid(pid_right, &mut status, 0);
 
-            return Ok(status);
+            Ok(exit_status(status))
         },
 
         Command::Sequence { first, second } => {
-            execute(first, context, built_ins)?;
+            let status = execute(first, context, built_ins)?;
+            context.last_exit_status = status;
+            if context.loop_control.is_some() {
+                return Ok(status);
+            }
             execute(second, context, built_ins)
         }
         Command::Redirect {
             child_command,
             redirect_type,
+            fd,
             target_file,
         } => {
-            let (std_fd, saved_fd) = unsafe {
-                match redirect_type {
-                    RedirectionType::In => (libc::STDIN_FILENO, libc::dup(libc::STDIN_FILENO)),
-                    _ => (libc::STDOUT_FILENO, libc::dup(libc::STDOUT_FILENO)),
+            let target = match redirect_type {
+                RedirectionType::HereDocument | RedirectionType::HereString => {
+                    expansion::expand_word_unsplit(target_file, context)
                 }
+                _ => expansion::expand_redirection_target(target_file, context),
             };
-
-            let file = match redirect_type {
-                RedirectionType::In => match File::options().read(true).open(target_file) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        return Err(ExecutionError::FileError(e));
-                    }
-                },
-                RedirectionType::Out => {
-                    match File::options().create(true).write(true).open(target_file) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            return Err(ExecutionError::FileError(e));
-                        }
-                    }
-                }
-                RedirectionType::Append => {
-                    match File::options().create(true).append(true).open(target_file) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            return Err(ExecutionError::FileError(e));
-                        }
-                    }
-                }
+            let target = match target {
+                Ok(target) => target,
+                Err(error) => return recover(error),
             };
+            let fd = *fd;
 
-            let file_fd = file.as_raw_fd();
+            let source = match open_redirection(redirect_type, &target, context) {
+                Ok(source) => source,
+                Err(error) => return recover(error),
+            };
 
-            std::mem::forget(file);
+            // Anything still buffered belongs to the old `fd` 1.
+            let _ = io::stdout().flush();
+            // Keep the original out of the way of the child's descriptors.
+            let saved_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
 
             unsafe {
-                libc::dup2(file_fd, std_fd);
-                libc::close(file_fd);
+                match source {
+                    Some((source_fd, opened)) if source_fd != fd => {
+                        libc::dup2(source_fd, fd);
+                        if opened {
+                            libc::close(source_fd);
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        libc::close(fd);
+                    }
+                }
             }
 
-            let status = execute(child_command, context, built_ins)?;
+            let status = execute(child_command, context, built_ins);
 
+            let _ = io::stdout().flush();
             unsafe {
-                libc::dup2(saved_fd, std_fd);
-                libc::close(saved_fd);
+                if saved_fd == -1 {
+                    libc::close(fd);
+                } else {
+                    libc::dup2(saved_fd, fd);
+                    libc::close(saved_fd);
+                }
             }
 
-            return Ok(status);
+            status
         }
         Command::Conditional {
             left,
             right,
             operator,
         } => {
-            let exit_code = execute(left, context, built_ins).unwrap_or(1);
+            let exit_code = condition_status(left, context, built_ins)?;
+            context.last_exit_status = exit_code;
+            if context.loop_control.is_some() {
+                return Ok(exit_code);
+            }
             if exit_code == 0 {
                 match operator {
-                    ConditionalType::And => {
-                        return execute(right, context, built_ins);
-                    }
-                    ConditionalType::Or => {
-                        return Ok(exit_code);
-                    }
+                    ConditionalType::And => execute(right, context, built_ins),
+                    ConditionalType::Or => Ok(exit_code),
                 }
             } else {
                 match operator {
-                    ConditionalType::And => {
-                        return Ok(exit_code);
-                    }
-                    ConditionalType::Or => {
-                        return execute(right, context, built_ins);
-                    }
+                    ConditionalType::And => Ok(exit_code),
+                    ConditionalType::Or => execute(right, context, built_ins),
+                }
+            }
+        }
+        Command::If {
+            branches,
+            else_body,
+        } => {
+            for (condition, body) in branches {
+                let status = condition_status(condition, context, built_ins)?;
+                context.last_exit_status = status;
+                if context.loop_control.is_some() {
+                    return Ok(status);
+                }
+                if status == 0 {
+                    return execute(body, context, built_ins);
                 }
             }
+            match else_body {
+                Some(body) => execute(body, context, built_ins),
+                None => Ok(0),
+            }
+        }
+        Command::While { condition, body } => in_loop(context, |context| {
+            run_loop(condition, body, false, context, built_ins)
+        }),
+        Command::Until { condition, body } => in_loop(context, |context| {
+            run_loop(condition, body, true, context, built_ins)
+        }),
+        Command::For { var, words, body } => {
+            let values = expansion::expand_words(words, context)?;
+            in_loop(context, |context| {
+                run_for(var, &values, body, context, built_ins)
+            })
+        }
+        Command::Case { word, items } => {
+            let subject = expansion::expand_word_unsplit(word, context)?;
+            run_case(&subject, items, context, built_ins)
         }
         Command::Background { child_command } => unsafe {
             let pid = libc::fork();
             if pid == -1 {
-                return Err(ExecutionError::ForkFailed);
+                Err(ExecutionError::ForkFailed)
             } else if pid == 0 {
-                let null_fd = File::open("/dev/null")
-                    .map_err(|e| ExecutionError::FileError(e))?
-                    .as_raw_fd();
-                libc::dup2(null_fd, libc::STDIN_FILENO);
-                libc::close(null_fd);
+                if let Ok(null) = File::open("/dev/null") {
+                    let null_fd = null.into_raw_fd();
+                    libc::dup2(null_fd, libc::STDIN_FILENO);
+                    libc::close(null_fd);
+                }
 
-                let exit_code = execute(child_command, context, built_ins).unwrap_or(1);
+                let exit_code = child_status(execute(child_command, context, built_ins));
 
                 libc::exit(exit_code);
             } else {
                 context.jobs.push(pid);
-                println!("[{}] {pid}", context.jobs.len());
-                return Ok(0);
+                context.last_background_pid = Some(pid);
+                if context.interactive {
+                    println!("[{}] {pid}", context.jobs.len());
+                }
+                Ok(0)
             }
         },
     }
 }
+
+/// Runs a simple command: a builtin in the shell itself, anything else in a
+/// forked child.
+fn execute_simple(
+    sc: &SimpleCommand,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ExecutionError> {
+    context.last_substitution_status = None;
+    let arguments = expansion::expand_words(&sc.words, context)?;
+
+    let Some(command) = arguments.first() else {
+        assign(&sc.assignments, context)?;
+        return Ok(context.last_substitution_status.unwrap_or(0));
+    };
+
+    // Assignments before a command only last while it runs, unless it is a
+    // special builtin.
+    let saved: Vec<(String, Option<Variable>)> =
+        if utils::SPECIAL_BUILT_INS.contains(&command.as_str()) {
+            Vec::new()
+        } else {
+            sc.assignments
+                .iter()
+                .map(|assignment| {
+                    let name = assignment.name.clone();
+                    let variable = context.variables.get(&name).cloned();
+                    (name, variable)
+                })
+                .collect()
+        };
+
+    let result =
+        assign(&sc.assignments, context).and_then(|()| match built_ins.get(command.as_str()) {
+            Some(closure) => closure(&arguments, context),
+            None => execute_external(command, &arguments, &sc.assignments, context),
+        });
+
+    for (name, variable) in saved.into_iter().rev() {
+        match variable {
+            Some(variable) => context.variables.insert(name, variable),
+            None => context.variables.remove(&name),
+        };
+    }
+    result
+}
+
+/// Expands and assigns `assignments` from left to right, so that each value
+/// sees the ones before it.
+fn assign(
+    assignments: &[Assignment],
+    context: &mut ExecutionContext,
+) -> Result<(), ExecutionError> {
+    for assignment in assignments {
+        let value = expansion::expand_assignment(&assignment.value, context)?;
+        context.set_variable(&assignment.name, value)?;
+    }
+    Ok(())
+}
+
+/// Runs `command` in a forked child, exporting the variables of the
+/// `assignments` before it to the child.
+fn execute_external(
+    command: &str,
+    arguments: &[String],
+    assignments: &[Assignment],
+    context: &mut ExecutionContext,
+) -> Result<i32, ExecutionError> {
+    unsafe {
+        let pid = libc::fork();
+        if pid == -1 {
+            Err(ExecutionError::ForkFailed)
+        } else if pid == 0 {
+            let mut action: libc::sigaction = std::mem::zeroed();
+
+            action.sa_sigaction = libc::SIG_DFL;
+
+            let signals_to_reset = [
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTSTP,
+                libc::SIGTTIN,
+                libc::SIGTTOU,
+            ];
+
+            for &signal in &signals_to_reset {
+                if libc::sigaction(signal, &action, std::ptr::null_mut()) == -1 {
+                    panic!(
+                        "Failed to reset signal handler in child for signal {}",
+                        signal
+                    );
+                }
+            }
+
+            // The child owns a copy of the context, so the assignments
+            // can be exported in it directly.
+            for assignment in assignments {
+                if let Some(variable) = context.variables.get_mut(&assignment.name) {
+                    variable.exported = true;
+                }
+            }
+
+            let c_args = arguments
+                .iter()
+                .map(|arg| CString::new(arg.clone()).unwrap())
+                .collect::<Vec<CString>>();
+
+            let mut argv = c_args
+                .iter()
+                .map(|arg| arg.as_ptr())
+                .collect::<Vec<*const libc::c_char>>();
+
+            argv.push(std::ptr::null());
+
+            let c_env = context.environment();
+
+            let mut envp = c_env
+                .iter()
+                .map(|var| var.as_ptr())
+                .collect::<Vec<*const libc::c_char>>();
+
+            envp.push(std::ptr::null());
+
+            let exit_code = exec_command(command, &argv, &envp, context);
+            libc::exit(exit_code);
+        } else {
+            // libc::tcsetpgrp(libc::STDIN_FILENO, pid);
+
+            let mut status = 0;
+            libc::waitpid(pid, &mut status, 0);
+
+            // libc::tcsetpgrp(libc::STDIN_FILENO, context.shell_pgid as i32);
+            Ok(exit_status(status))
+        }
+    }
+}
+
+/// Opens what `redirect_type` points `target` at, returning the descriptor to
+/// install and whether it was opened here, or `None` to close the descriptor.
+fn open_redirection(
+    redirect_type: &RedirectionType,
+    target: &str,
+    context: &ExecutionContext,
+) -> Result<Option<(i32, bool)>, ExecutionError> {
+    Ok(match redirect_type {
+        RedirectionType::HereDocument => Some((here_document(target)?, true)),
+        RedirectionType::HereString => Some((here_document(&format!("{target}\n"))?, true)),
+        RedirectionType::DuplicateIn | RedirectionType::DuplicateOut => {
+            if target == "-" {
+                None
+            } else {
+                let source_fd = target
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|&source_fd| unsafe { libc::fcntl(source_fd, libc::F_GETFD) } != -1)
+                    .ok_or_else(|| ExecutionError::BadFileDescriptor(target.to_string()))?;
+                Some((source_fd, false))
+            }
+        }
+        RedirectionType::Out if context.noclobber => {
+            // Creating the file exclusively checks and creates in one step.
+            // Only an existing file that is not a regular one, such as
+            // `/dev/null`, may still be written to.
+            let file = match File::options().write(true).create_new(true).open(target) {
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    if fs::metadata(target).is_ok_and(|metadata| metadata.is_file()) {
+                        return Err(ExecutionError::FileExists(target.to_string()));
+                    }
+                    File::options().write(true).open(target)
+                }
+                result => result,
+            }
+            .map_err(|error| ExecutionError::FileError(target.to_string(), error))?;
+            Some((file.into_raw_fd(), true))
+        }
+        _ => {
+            let mut options = File::options();
+            match redirect_type {
+                RedirectionType::In => options.read(true),
+                RedirectionType::Out | RedirectionType::Clobber => {
+                    options.create(true).write(true).truncate(true)
+                }
+                RedirectionType::Append => options.create(true).append(true),
+                RedirectionType::ReadWrite => options.create(true).read(true).write(true),
+                _ => unreachable!(),
+            };
+            let file = options
+                .open(target)
+                .map_err(|error| ExecutionError::FileError(target.to_string(), error))?;
+            Some((file.into_raw_fd(), true))
+        }
+    })
+}
+
+/// Reports a failure that only fails its command on the current standard
+/// error, where redirections of the command still apply, and turns it into a
+/// nonzero status. Fatal errors are passed on.
+fn recover(error: ExecutionError) -> Result<i32, ExecutionError> {
+    if error.is_fatal() {
+        return Err(error);
+    }
+    eprintln!("flash: {error}");
+    Ok(error.exit_status())
+}
+
+/// Turns the result of a command run in a forked child into the status the
+/// child exits with, reporting any error first since nothing else will.
+fn child_status(result: Result<i32, ExecutionError>) -> i32 {
+    result.unwrap_or_else(|error| {
+        eprintln!("flash: {error}");
+        error.exit_status()
+    })
+}
+
+/// Replaces the current process with `command`, searching `$PATH` when the
+/// name contains no `/`. Only returns if no executable could be run, with the
+/// exit status to use.
+unsafe fn exec_command(
+    command: &str,
+    argv: &[*const libc::c_char],
+    envp: &[*const libc::c_char],
+    context: &ExecutionContext,
+) -> i32 {
+    let candidates: Vec<String> = if command.contains('/') {
+        vec![command.to_string()]
+    } else {
+        context
+            .variable("PATH")
+            .unwrap_or_else(|| "/usr/bin:/bin".to_string())
+            .split(':')
+            .map(|dir| match dir {
+                "" => command.to_string(),
+                dir => format!("{}/{command}", dir.trim_end_matches('/')),
+            })
+            .collect()
+    };
+
+    let mut permission_denied = false;
+    for candidate in candidates {
+        let Ok(path) = CString::new(candidate) else {
+            continue;
+        };
+        unsafe {
+            libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+        }
+        if io::Error::last_os_error().raw_os_error() == Some(libc::EACCES) {
+            permission_denied = true;
+        }
+    }
+
+    if permission_denied {
+        eprintln!("flash: {command}: Permission denied");
+        126
+    } else {
+        eprintln!("flash: {command}: command not found");
+        127
+    }
+}
+
+/// Runs the condition of a compound command. A failure there is reported and
+/// counts as a nonzero status, as for the left side of `&&` and `||`.
+fn condition_status(
+    condition: &Command,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ExecutionError> {
+    execute(condition, context, built_ins).or_else(recover)
+}
+
+/// Runs `run` with the loop depth raised, so that `break` and `continue`
+/// inside it have a loop to act on.
+fn in_loop(
+    context: &mut ExecutionContext,
+    run: impl FnOnce(&mut ExecutionContext) -> Result<i32, ExecutionError>,
+) -> Result<i32, ExecutionError> {
+    context.loop_depth += 1;
+    let result = run(context);
+    context.loop_depth -= 1;
+    result
+}
+
+/// Runs `body` while `condition` succeeds, or until it does when `until` is
+/// set, returning the status of the last body run.
+fn run_loop(
+    condition: &Command,
+    body: &Command,
+    until: bool,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ExecutionError> {
+    let mut status = 0;
+
+    loop {
+        let test_status = condition_status(condition, context, built_ins)?;
+        context.last_exit_status = test_status;
+        match take_loop_control(context) {
+            Some(LoopControl::Continue(1)) => continue,
+            Some(_) => break,
+            None if (test_status == 0) == until => break,
+            None => {}
+        }
+
+        status = execute(body, context, built_ins)?;
+        context.last_exit_status = status;
+        match take_loop_control(context) {
+            Some(LoopControl::Continue(1)) | None => {}
+            Some(_) => break,
+        }
+    }
+
+    Ok(status)
+}
+
+/// Runs the list of the first clause with a pattern matching `subject`, then
+/// carries on as its terminator says. Returns 0 when nothing matches.
+fn run_case(
+    subject: &str,
+    items: &[CaseItem],
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ExecutionError> {
+    let mut status = 0;
+    let mut fall_through = false;
+
+    for item in items {
+        if !fall_through && !case_matches(subject, &item.patterns, context)? {
+            continue;
+        }
+
+        if let Some(body) = &item.body {
+            status = execute(body, context, built_ins)?;
+            context.last_exit_status = status;
+            if context.loop_control.is_some() {
+                return Ok(status);
+            }
+        }
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => fall_through = true,
+            CaseTerminator::Continue => fall_through = false,
+        }
+    }
+
+    Ok(status)
+}
+
+/// Returns whether any of `patterns` matches `subject`. Patterns are expanded
+/// in order, stopping at the first match.
+fn case_matches(
+    subject: &str,
+    patterns: &[Word],
+    context: &mut ExecutionContext,
+) -> Result<bool, ExecutionError> {
+    for word in patterns {
+        if pattern::matches(&expansion::expand_case_pattern(word, context)?, subject) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Runs `body` once for each of `values`, assigned to `var` in turn, and
+/// returns the status of the last run.
+fn run_for(
+    var: &str,
+    values: &[String],
+    body: &Command,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ExecutionError> {
+    let mut status = 0;
+
+    for value in values {
+        if let Err(error) = context.set_variable(var, value.clone()) {
+            return recover(error);
+        }
+        status = execute(body, context, built_ins)?;
+        context.last_exit_status = status;
+        match take_loop_control(context) {
+            Some(LoopControl::Continue(1)) | None => {}
+            Some(_) => break,
+        }
+    }
+
+    Ok(status)
+}
+
+/// Takes the pending `break` or `continue` for the innermost loop. One aimed
+/// further out stays pending with a level less, so the loop returns and the
+/// outer loops see it.
+fn take_loop_control(context: &mut ExecutionContext) -> Option<LoopControl> {
+    let control = context.loop_control.take()?;
+    context.loop_control = match control {
+        LoopControl::Break(1) | LoopControl::Continue(1) => None,
+        LoopControl::Break(levels) => Some(LoopControl::Break(levels - 1)),
+        LoopControl::Continue(levels) => Some(LoopControl::Continue(levels - 1)),
+    };
+    Some(control)
+}
+
+/// Returns a descriptor that reads `content` from the start, backed by an
+/// unlinked temporary file so that bodies of any size fit. `mkstemp` picks an
+/// unpredictable name and creates the file exclusively.
+fn here_document(content: &str) -> Result<i32, ExecutionError> {
+    let template = env::temp_dir().join("flash-heredoc-XXXXXX");
+    let mut template = CString::new(template.into_os_string().into_vec())
+        .map_err(|_| ExecutionError::InvalidPath)?
+        .into_bytes_with_nul();
+
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr().cast()) };
+    let path = || String::from_utf8_lossy(&template[..template.len() - 1]).into_owned();
+    if fd == -1 {
+        return Err(ExecutionError::FileError(
+            path(),
+            io::Error::last_os_error(),
+        ));
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    unsafe {
+        libc::unlink(template.as_ptr().cast());
+    }
+
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .map_err(|error| ExecutionError::FileError(path(), error))?;
+    Ok(file.into_raw_fd())
+}
+
+/// Runs `node` in a forked child with its standard output captured through a
+/// pipe, returning the output and the child's exit status.
+pub fn capture_output(
+    node: &Command,
+    context: &mut ExecutionContext,
+) -> Result<(String, i32), ExecutionError> {
+    unsafe {
+        let mut pipe_fd = [0; 2];
+        if libc::pipe(pipe_fd.as_mut_ptr()) == -1 {
+            return Err(ExecutionError::PipeError(io::Error::last_os_error()));
+        }
+
+        let pid = libc::fork();
+        if pid == -1 {
+            libc::close(pipe_fd[0]);
+            libc::close(pipe_fd[1]);
+            return Err(ExecutionError::ForkFailed);
+        } else if pid == 0 {
+            libc::close(pipe_fd[0]);
+            libc::dup2(pipe_fd[1], STDOUT_FILENO);
+            libc::close(pipe_fd[1]);
+
+            let exit_code = child_status(execute(node, context, &utils::built_ins()));
+            io::stdout().flush().ok();
+
+            libc::exit(exit_code);
+        }
+
+        libc::close(pipe_fd[1]);
+
+        let mut output = Vec::new();
+        let read_result = File::from_raw_fd(pipe_fd[0]).read_to_end(&mut output);
+
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+        read_result.map_err(ExecutionError::PipeError)?;
+
+        Ok((
+            String::from_utf8_lossy(&output).into_owned(),
+            exit_status(status),
+        ))
+    }
+}
+
+/// Converts a status reported by `waitpid` into a shell exit status.
+fn exit_status(status: i32) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        status
+    }
+}
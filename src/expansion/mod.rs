@@ -0,0 +1,758 @@
+use std::{
+    ffi::{CStr, CString},
+    mem,
+};
+
+use crate::{
+    arithmetic, executor, lexer, parser, pattern,
+    utils::{ExecutionContext, ExecutionError, Word, WordSegment, is_valid_name},
+};
+
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// A piece of an expanded word. Quoted text is taken literally by the later
+/// expansion stages.
+#[derive(Debug, Clone)]
+struct Fragment {
+    text: String,
+    quoted: bool,
+    /// Set for unquoted results of expansions, which undergo field splitting.
+    splittable: bool,
+}
+
+impl Fragment {
+    fn literal(text: String, quoted: bool) -> Fragment {
+        Fragment {
+            text,
+            quoted,
+            splittable: false,
+        }
+    }
+
+    fn expanded(text: String, quoted: bool) -> Fragment {
+        Fragment {
+            text,
+            quoted,
+            splittable: !quoted,
+        }
+    }
+}
+
+/// The result of expanding a single parameter.
+enum ParameterValue {
+    Single(String),
+    /// The expanded word of an operator such as `${name:-word}`.
+    Word(Vec<Fragment>),
+    /// `$@` and `$*` produce one field per positional parameter.
+    Fields(Vec<String>),
+}
+
+pub fn expand_words(
+    words: &[Word],
+    context: &mut ExecutionContext,
+) -> Result<Vec<String>, ExecutionError> {
+    let mut fields = Vec::new();
+    for word in words {
+        fields.extend(expand_word(word, context)?);
+    }
+    Ok(fields)
+}
+
+pub fn expand_word(
+    word: &Word,
+    context: &mut ExecutionContext,
+) -> Result<Vec<String>, ExecutionError> {
+    let fields = expand_fragments(word, false, context).map_err(|error| at_word(error, word))?;
+    let ifs = context
+        .variable("IFS")
+        .unwrap_or_else(|| DEFAULT_IFS.to_string());
+
+    let mut expanded = Vec::new();
+    for field in fields {
+        if field
+            .iter()
+            .any(|fragment| fragment.quoted || !fragment.text.is_empty())
+        {
+            for field in split_field(field, &ifs) {
+                expanded.extend(expand_pathname(&field));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Replaces a field containing unquoted pattern characters with the sorted
+/// paths it matches, leaving it unchanged when nothing matches.
+fn expand_pathname(field: &[Fragment]) -> Vec<String> {
+    let literal: String = field
+        .iter()
+        .map(|fragment| fragment.text.as_str())
+        .collect();
+
+    if field.iter().all(|fragment| fragment.quoted) {
+        return vec![literal];
+    }
+
+    let pattern = to_pattern(field);
+    if !pattern::has_wildcards(&pattern) {
+        return vec![literal];
+    }
+
+    let matches = pattern::glob(&pattern);
+    if matches.is_empty() {
+        vec![literal]
+    } else {
+        matches
+    }
+}
+
+/// Turns a field into a pattern in which its quoted parts match literally.
+fn to_pattern(field: &[Fragment]) -> String {
+    field
+        .iter()
+        .map(|fragment| {
+            if fragment.quoted {
+                pattern::escape(&fragment.text)
+            } else {
+                fragment.text.clone()
+            }
+        })
+        .collect()
+}
+
+/// Expands a `case` pattern without field splitting or pathname expansion.
+/// Quoted parts of the word are escaped so that they match literally.
+pub fn expand_case_pattern(
+    word: &Word,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let fields = expand_fragments(word, false, context).map_err(|error| at_word(error, word))?;
+    Ok(fields
+        .iter()
+        .map(|field| to_pattern(field))
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+/// Expands the target of a redirection, which has to result in exactly one
+/// field.
+pub fn expand_redirection_target(
+    word: &Word,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let mut fields = expand_word(word, context)?;
+    if fields.len() != 1 {
+        let text = context
+            .source
+            .get(word.span.start..word.span.end)
+            .map_or_else(|| word.to_string(), str::to_string);
+        return Err(ExecutionError::AmbiguousRedirect(text));
+    }
+    Ok(fields.remove(0))
+}
+
+/// Expands the value of a `name=value` assignment. Tilde expansion also
+/// applies after each `:`, and the result is neither split nor globbed.
+pub fn expand_assignment(
+    word: &Word,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let fields = expand_fragments(word, true, context).map_err(|error| at_word(error, word))?;
+    Ok(join_fields(fields))
+}
+
+/// Expands a word without field splitting or pathname expansion, as needed
+/// for a here-document body or a here-string.
+pub fn expand_word_unsplit(
+    word: &Word,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let fields = expand_fragments(word, false, context).map_err(|error| at_word(error, word))?;
+    Ok(join_fields(fields))
+}
+
+fn join_fields(fields: Vec<Vec<Fragment>>) -> String {
+    fields
+        .into_iter()
+        .map(|field| {
+            field
+                .into_iter()
+                .map(|fragment| fragment.text)
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Attaches the span of `word` to an error raised while expanding it.
+fn at_word(error: ExecutionError, word: &Word) -> ExecutionError {
+    ExecutionError::Expansion {
+        error: Box::new(error),
+        span: word.span,
+    }
+}
+
+fn expand_fragments(
+    word: &Word,
+    assignment: bool,
+    context: &mut ExecutionContext,
+) -> Result<Vec<Vec<Fragment>>, ExecutionError> {
+    let mut fields: Vec<Vec<Fragment>> = vec![Vec::new()];
+
+    for (idx, segment) in word.segments.iter().enumerate() {
+        let fragment = match segment {
+            WordSegment::Unquoted(text) if idx == 0 && assignment => {
+                let whole_word = word.segments.len() == 1;
+                let parts: Vec<&str> = text.split(':').collect();
+                for (part_idx, part) in parts.iter().enumerate() {
+                    let is_last = part_idx == parts.len() - 1;
+                    if part_idx > 0 {
+                        fields
+                            .last_mut()
+                            .unwrap()
+                            .push(Fragment::literal(":".to_string(), false));
+                    }
+                    let expanded = if part.starts_with('~') {
+                        expand_tilde(part, whole_word || !is_last, context)
+                    } else {
+                        None
+                    };
+                    let fragments = match expanded {
+                        Some((directory, rest)) => vec![
+                            Fragment::literal(directory, true),
+                            Fragment::literal(rest.to_string(), false),
+                        ],
+                        None => vec![Fragment::literal(part.to_string(), false)],
+                    };
+                    fields.last_mut().unwrap().extend(fragments);
+                }
+                continue;
+            }
+            WordSegment::Unquoted(text) if idx == 0 && text.starts_with('~') => {
+                match expand_tilde(text, word.segments.len() == 1, context) {
+                    Some((directory, rest)) => {
+                        // The directory is not split or globbed any further.
+                        fields
+                            .last_mut()
+                            .unwrap()
+                            .push(Fragment::literal(directory, true));
+                        Fragment::literal(rest.to_string(), false)
+                    }
+                    None => Fragment::literal(text.clone(), false),
+                }
+            }
+            WordSegment::Unquoted(text) => Fragment::literal(text.clone(), false),
+            WordSegment::DoubleQuoted(text) | WordSegment::SingleQuoted(text) => {
+                Fragment::literal(text.clone(), true)
+            }
+            WordSegment::CommandSubstitution { command, quoted } => {
+                Fragment::expanded(substitute_command(command, context)?, *quoted)
+            }
+            WordSegment::Arithmetic { expression, quoted } => {
+                Fragment::expanded(expand_arithmetic(expression, context)?, *quoted)
+            }
+            WordSegment::Parameter { expression, quoted } => {
+                match expand_parameter(expression, *quoted, context)? {
+                    ParameterValue::Single(value) => Fragment::expanded(value, *quoted),
+                    ParameterValue::Word(fragments) => {
+                        fields.last_mut().unwrap().extend(fragments);
+                        continue;
+                    }
+                    ParameterValue::Fields(values) => {
+                        for (idx, value) in values.into_iter().enumerate() {
+                            if idx > 0 {
+                                fields.push(Vec::new());
+                            }
+                            fields
+                                .last_mut()
+                                .unwrap()
+                                .push(Fragment::expanded(value, *quoted));
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+
+        fields.last_mut().unwrap().push(fragment);
+    }
+
+    Ok(fields)
+}
+
+/// Returns whether `ch` is IFS whitespace: a space, tab or newline in `ifs`.
+pub fn is_ifs_whitespace(ch: char, ifs: &str) -> bool {
+    matches!(ch, ' ' | '\t' | '\n') && ifs.contains(ch)
+}
+
+/// Splits the unquoted expansion results in `field` on the characters of
+/// `ifs`. Runs of IFS whitespace separate fields and are dropped at either
+/// end, while every other IFS character (with any surrounding whitespace)
+/// delimits exactly one field, which may be empty.
+fn split_field(field: Vec<Fragment>, ifs: &str) -> Vec<Vec<Fragment>> {
+    let is_ifs_whitespace = |ch: char| is_ifs_whitespace(ch, ifs);
+
+    let mut fields = Vec::new();
+    let mut current: Vec<Fragment> = Vec::new();
+    // Whether `current` has to be kept as a field even if it ends up empty.
+    let mut has_content = false;
+
+    for fragment in field {
+        if !fragment.splittable || ifs.is_empty() {
+            has_content |= fragment.quoted || !fragment.text.is_empty();
+            current.push(fragment);
+            continue;
+        }
+
+        let mut text = String::new();
+        let mut chars = fragment.text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if !ifs.contains(ch) {
+                text.push(ch);
+                continue;
+            }
+
+            let mut delimits_empty_field = !is_ifs_whitespace(ch);
+            while let Some(&next) = chars.peek() {
+                if is_ifs_whitespace(next) {
+                    chars.next();
+                } else if ifs.contains(next) && !delimits_empty_field {
+                    delimits_empty_field = true;
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            if !text.is_empty() {
+                current.push(Fragment::expanded(std::mem::take(&mut text), false));
+                has_content = true;
+            }
+            if has_content || delimits_empty_field {
+                fields.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            has_content = false;
+        }
+
+        if !text.is_empty() {
+            current.push(Fragment::expanded(text, false));
+            has_content = true;
+        }
+    }
+
+    if has_content {
+        fields.push(current);
+    }
+    fields
+}
+
+fn expand_parameter(
+    expression: &str,
+    quoted: bool,
+    context: &mut ExecutionContext,
+) -> Result<ParameterValue, ExecutionError> {
+    if let Some(name) = expression.strip_prefix('#')
+        && !name.is_empty()
+    {
+        if !split_name(name).1.is_empty() {
+            return Err(ExecutionError::BadSubstitution(expression.to_string()));
+        }
+        let length = match name {
+            "@" | "*" => context.positional_parameters.len(),
+            _ => lookup_parameter(name, context)
+                .unwrap_or_default()
+                .chars()
+                .count(),
+        };
+        return Ok(ParameterValue::Single(length.to_string()));
+    }
+
+    let (name, operation) = split_name(expression);
+    if name.is_empty() {
+        return Err(ExecutionError::BadSubstitution(expression.to_string()));
+    }
+
+    if operation.is_empty() {
+        return Ok(match name {
+            "@" => ParameterValue::Fields(context.positional_parameters.clone()),
+            "*" if quoted => {
+                // `"$*"` joins the parameters with the first character of IFS.
+                let separator = match context.variable("IFS") {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_string(),
+                };
+                ParameterValue::Single(context.positional_parameters.join(&separator))
+            }
+            "*" => ParameterValue::Fields(context.positional_parameters.clone()),
+            _ => ParameterValue::Single(lookup_parameter(name, context).unwrap_or_default()),
+        });
+    }
+
+    let value = match name {
+        "@" | "*" if !context.positional_parameters.is_empty() => {
+            Some(context.positional_parameters.join(" "))
+        }
+        _ => lookup_parameter(name, context),
+    };
+
+    // Trimming operators take a pattern rather than a word.
+    for operator in ["%%", "%", "##", "#"] {
+        if let Some(pattern) = operation.strip_prefix(operator) {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(pattern, context)?;
+            return Ok(ParameterValue::Single(trim(&value, &pattern, operator)));
+        }
+    }
+
+    // With a colon, a set but empty parameter is treated as unset.
+    let (check_null, operation) = match operation.strip_prefix(':') {
+        Some(operation) => (true, operation),
+        None => (false, operation),
+    };
+    let mut chars = operation.chars();
+    let operator = chars.next();
+    let word = chars.as_str();
+
+    let value = value.filter(|value| !(check_null && value.is_empty()));
+
+    match (operator, value) {
+        (Some('-' | '=' | '?'), Some(value)) => Ok(ParameterValue::Single(value)),
+        (Some('+'), None) => Ok(ParameterValue::Single(String::new())),
+        (Some('-' | '+'), _) => Ok(ParameterValue::Word(expand_operand(word, quoted, context)?)),
+        (Some('='), None) => {
+            if !is_valid_name(name) {
+                return Err(ExecutionError::BadSubstitution(expression.to_string()));
+            }
+            let fragments = expand_operand(word, quoted, context)?;
+            let value: String = fragments
+                .iter()
+                .map(|fragment| fragment.text.as_str())
+                .collect();
+            context.set_variable(name, value)?;
+            Ok(ParameterValue::Word(fragments))
+        }
+        (Some('?'), None) => {
+            let message = expand_operand(word, true, context)?
+                .into_iter()
+                .map(|fragment| fragment.text)
+                .collect::<String>();
+            let message = if message.is_empty() {
+                "parameter null or not set".to_string()
+            } else {
+                message
+            };
+            Err(ExecutionError::ParameterNullOrUnset {
+                name: name.to_string(),
+                message,
+            })
+        }
+        _ => Err(ExecutionError::BadSubstitution(expression.to_string())),
+    }
+}
+
+/// Splits the parameter name off the front of a `${...}` expression.
+fn split_name(expression: &str) -> (&str, &str) {
+    let end = match expression.chars().next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => expression
+            .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+            .unwrap_or(expression.len()),
+        Some(ch) if ch.is_ascii_digit() => expression
+            .find(|ch: char| !ch.is_ascii_digit())
+            .unwrap_or(expression.len()),
+        Some(ch) if "?$!#@*-".contains(ch) => 1,
+        _ => 0,
+    };
+    expression.split_at(end)
+}
+
+/// Expands the word operand of `${name:-word}` and friends. Inside double
+/// quotes the whole result is quoted, otherwise all of its unquoted text is
+/// subject to field splitting.
+fn expand_operand(
+    text: &str,
+    quoted: bool,
+    context: &mut ExecutionContext,
+) -> Result<Vec<Fragment>, ExecutionError> {
+    let word = lexer::word(text).map_err(|_| ExecutionError::BadSubstitution(text.to_string()))?;
+    let fields = expand_fragments(&word, false, context)?;
+
+    let mut fragments = Vec::new();
+    for (idx, field) in fields.into_iter().enumerate() {
+        if idx > 0 {
+            fragments.push(Fragment::expanded(" ".to_string(), quoted));
+        }
+        // Unquoted text in the operand is split along with the expansion.
+        fragments.extend(
+            field
+                .into_iter()
+                .map(|fragment| Fragment::expanded(fragment.text, quoted || fragment.quoted)),
+        );
+    }
+    Ok(fragments)
+}
+
+/// Expands the pattern operand of a trimming operator. Quoted parts of the
+/// pattern match literally.
+fn expand_pattern(text: &str, context: &mut ExecutionContext) -> Result<String, ExecutionError> {
+    Ok(to_pattern(&expand_operand(text, false, context)?))
+}
+
+/// Removes the shortest or longest prefix or suffix of `value` matching
+/// `pattern`.
+fn trim(value: &str, pattern: &str, operator: &str) -> String {
+    let mut boundaries: Vec<usize> = value.char_indices().map(|(idx, _)| idx).collect();
+    boundaries.push(value.len());
+
+    match operator {
+        "#" | "##" => {
+            if operator == "##" {
+                boundaries.reverse();
+            }
+            boundaries
+                .into_iter()
+                .find(|&idx| pattern::matches(pattern, &value[..idx]))
+                .map(|idx| value[idx..].to_string())
+        }
+        _ => {
+            if operator == "%" {
+                boundaries.reverse();
+            }
+            boundaries
+                .into_iter()
+                .find(|&idx| pattern::matches(pattern, &value[idx..]))
+                .map(|idx| value[..idx].to_string())
+        }
+    }
+    .unwrap_or_else(|| value.to_string())
+}
+
+/// Expands the tilde prefix at the start of `text`, returning the directory
+/// and the rest of the text. The prefix runs up to the first `/`, so when it
+/// is not followed by one the segment must make up the whole word; otherwise
+/// part of the prefix would be quoted or expanded and it is left alone.
+fn expand_tilde<'a>(
+    text: &'a str,
+    whole_word: bool,
+    context: &ExecutionContext,
+) -> Option<(String, &'a str)> {
+    let (prefix, rest) = match text.find('/') {
+        Some(idx) => text.split_at(idx),
+        None if whole_word => (text, ""),
+        None => return None,
+    };
+
+    let directory = match &prefix[1..] {
+        "" => context.variable("HOME").or_else(|| user_home(None))?,
+        "+" => context.variable("PWD")?,
+        "-" => context.variable("OLDPWD")?,
+        user => user_home(Some(user))?,
+    };
+
+    Some((directory, rest))
+}
+
+/// Looks up the home directory of `user`, or of the current user, in the
+/// password database.
+fn user_home(user: Option<&str>) -> Option<String> {
+    unsafe {
+        let entry = match user {
+            Some(user) => {
+                let c_user = CString::new(user).ok()?;
+                libc::getpwnam(c_user.as_ptr())
+            }
+            None => libc::getpwuid(libc::getuid()),
+        };
+        if entry.is_null() || (*entry).pw_dir.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*entry).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Runs `command` in a subshell and returns its output without trailing
+/// newlines.
+fn substitute_command(
+    command: &str,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let invalid = || ExecutionError::InvalidCommandSubstitution(command.to_string());
+
+    let tokens = lexer::tokenization(command.to_string()).map_err(|_| invalid())?;
+    if parser::is_empty(&tokens) {
+        return Ok(String::new());
+    }
+    let node = parser::parse(tokens).map_err(|_| invalid())?;
+
+    let source = mem::replace(&mut context.source, command.to_string());
+    let result = executor::capture_output(&node, context);
+    context.source = source;
+    let (mut output, status) = result?;
+    context.last_exit_status = status;
+    context.last_substitution_status = Some(status);
+
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+/// Expands parameters and command substitutions inside `expression` and
+/// evaluates the result.
+fn expand_arithmetic(
+    expression: &str,
+    context: &mut ExecutionContext,
+) -> Result<String, ExecutionError> {
+    let expression: String = expand_operand(expression, true, context)?
+        .into_iter()
+        .map(|fragment| fragment.text)
+        .collect();
+
+    arithmetic::evaluate(&expression, context)
+        .map(|value| value.to_string())
+        .map_err(ExecutionError::Arithmetic)
+}
+
+/// Looks up a variable, positional or special parameter, returning `None`
+/// when it is unset.
+fn lookup_parameter(name: &str, context: &ExecutionContext) -> Option<String> {
+    match name {
+        "?" => Some(context.last_exit_status.to_string()),
+        "$" => Some(context.shell_pid.to_string()),
+        "!" => context.last_background_pid.map(|pid| pid.to_string()),
+        "#" => Some(context.positional_parameters.len().to_string()),
+        "-" => Some(String::new()),
+        "0" => Some(context.shell_name.clone()),
+        _ if name.chars().all(|ch| ch.is_ascii_digit()) => {
+            let index: usize = name.parse().ok()?;
+            context
+                .positional_parameters
+                .get(index.checked_sub(1)?)
+                .cloned()
+        }
+        _ => context.variable(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn fields(field: Vec<Fragment>, ifs: &str) -> Vec<String> {
+        split_field(field, ifs)
+            .into_iter()
+            .map(|field| field.into_iter().map(|fragment| fragment.text).collect())
+            .collect()
+    }
+
+    fn split(text: &str, ifs: &str) -> Vec<String> {
+        fields(vec![Fragment::expanded(text.to_string(), false)], ifs)
+    }
+
+    #[test]
+    fn whitespace_delimiters() {
+        assert_eq!(split("  a  b  ", DEFAULT_IFS), ["a", "b"]);
+        assert_eq!(split("a\n\tb", DEFAULT_IFS), ["a", "b"]);
+        assert!(split("   ", DEFAULT_IFS).is_empty());
+        assert!(split("", DEFAULT_IFS).is_empty());
+    }
+
+    #[test]
+    fn non_whitespace_delimiters() {
+        assert_eq!(split("a::b", ":"), ["a", "", "b"]);
+        assert_eq!(split(":a", ":"), ["", "a"]);
+        assert_eq!(split("a:", ":"), ["a"]);
+        assert_eq!(split("::", ":"), ["", ""]);
+        // Only characters in IFS split.
+        assert_eq!(split("a b", ":"), ["a b"]);
+    }
+
+    #[test]
+    fn mixed_delimiters() {
+        assert_eq!(split(" a : b ", " :"), ["a", "b"]);
+        assert_eq!(split("a : : b", " :"), ["a", "", "b"]);
+        assert_eq!(split(" : a", " :"), ["", "a"]);
+        assert_eq!(split("a:", " :"), ["a"]);
+    }
+
+    #[test]
+    fn empty_ifs_does_not_split() {
+        assert_eq!(split(" a b ", ""), [" a b "]);
+    }
+
+    #[test]
+    fn only_expansion_results_split() {
+        let field = vec![
+            Fragment::literal("x".to_string(), false),
+            Fragment::expanded(" y z".to_string(), false),
+            Fragment::expanded(" q r".to_string(), true),
+        ];
+        assert_eq!(fields(field, DEFAULT_IFS), ["x", "y", "z q r"]);
+
+        // A quoted empty string is kept as a field of its own.
+        let field = vec![Fragment::literal(String::new(), true)];
+        assert_eq!(fields(field, DEFAULT_IFS), [""]);
+    }
+
+    #[test]
+    fn trim_prefixes_and_suffixes() {
+        assert_eq!(trim("a/b/c", "*/", "#"), "b/c");
+        assert_eq!(trim("a/b/c", "*/", "##"), "c");
+        assert_eq!(trim("f.tar.gz", ".*", "%"), "f.tar");
+        assert_eq!(trim("f.tar.gz", ".*", "%%"), "f");
+        // A pattern that matches nothing leaves the value alone.
+        assert_eq!(trim("abc", "x*", "#"), "abc");
+        assert_eq!(trim("abc", "", "%%"), "abc");
+        assert_eq!(trim("ééa", "?", "#"), "éa");
+    }
+
+    fn context() -> ExecutionContext {
+        let mut context = ExecutionContext {
+            variables: HashMap::new(),
+            jobs: vec![],
+            shell_pgid: 0,
+            shell_pid: 0,
+            last_exit_status: 0,
+            last_background_pid: None,
+            shell_name: "flash".to_string(),
+            positional_parameters: vec![],
+            last_substitution_status: None,
+            interactive: false,
+            noclobber: false,
+            loop_depth: 0,
+            loop_control: None,
+            source: String::new(),
+        };
+        for (name, value) in [("HOME", "/home/me"), ("PWD", "/here"), ("OLDPWD", "/there")] {
+            context.set_variable(name, value.to_string()).unwrap();
+        }
+        context
+    }
+
+    #[test]
+    fn tilde_prefixes() {
+        let context = context();
+        let tilde = |text| expand_tilde(text, true, &context);
+        assert_eq!(tilde("~"), Some(("/home/me".to_string(), "")));
+        assert_eq!(tilde("~/a/b"), Some(("/home/me".to_string(), "/a/b")));
+        assert_eq!(tilde("~+/a"), Some(("/here".to_string(), "/a")));
+        assert_eq!(tilde("~-"), Some(("/there".to_string(), "")));
+        assert_eq!(tilde("~root/a"), Some(("/root".to_string(), "/a")));
+        assert_eq!(tilde("~no-such-user"), None);
+    }
+
+    #[test]
+    fn tilde_prefix_must_end_the_segment() {
+        let context = context();
+        // A prefix without a `/` is only expanded when it is the whole word.
+        assert_eq!(expand_tilde("~", false, &context), None);
+        assert_eq!(
+            expand_tilde("~/a", false, &context),
+            Some(("/home/me".to_string(), "/a"))
+        );
+    }
+}
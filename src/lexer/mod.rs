@@ -1,188 +1,943 @@
-use crate::utils::{LexerError, LexerState, Token};
+use std::{
+    cell::Cell,
+    iter::{self, Peekable},
+    mem,
+    str::CharIndices,
+};
 
-pub fn tokenization(input: String) -> Result<Vec<Token>, LexerError> {
-    let input = input.trim();
+use crate::{
+    parser,
+    utils::{LexerError, LexerState, ParserError, Span, SpannedToken, Token, Word, WordSegment},
+};
+
+/// A character iterator that also knows the byte offset of the next character.
+struct Cursor<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// Byte offsets at which each line starts.
+    line_starts: Vec<usize>,
+    /// The offset and column of the last span, so that columns on a long line
+    /// are counted on from there rather than from the start of the line.
+    last_column: Cell<(usize, usize)>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Cursor<'a> {
+        let line_starts = iter::once(0)
+            .chain(input.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Cursor {
+            input,
+            chars: input.char_indices().peekable(),
+            line_starts,
+            last_column: Cell::new((0, 1)),
+        }
+    }
+
+    fn skip_to(&mut self, offset: usize) {
+        while self.offset() < offset {
+            self.next();
+        }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let line = self
+            .line_starts
+            .partition_point(|&line_start| line_start <= start);
+        let line_start = self.line_starts[line - 1];
+        let column = match self.last_column.get() {
+            (offset, column) if (line_start..=start).contains(&offset) => {
+                column + self.input[offset..start].chars().count()
+            }
+            _ => self.input[line_start..start].chars().count() + 1,
+        };
+        self.last_column.set((start, column));
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// The span from `start` to the end of input, for unterminated constructs.
+    fn span_from(&self, start: usize) -> Span {
+        self.span(start, self.input.len())
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek().map(|(_, ch)| ch)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.input.len(), |(offset, _)| *offset)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        self.chars.next().map(|(_, ch)| ch)
+    }
+}
+
+pub fn tokenization(input: String) -> Result<Vec<SpannedToken>, LexerError> {
+    lex(input.trim_end(), false)
+}
+
+/// Lexes the whole of `input` as a single word, as needed for the word of a
+/// `${name:-word}` style expansion. Blanks and operators are taken literally.
+pub fn word(input: &str) -> Result<Word, LexerError> {
+    let tokens = lex(input, true)?;
+    match tokens.into_iter().next().map(|spanned| spanned.token) {
+        Some(Token::Word(word)) => Ok(word),
+        _ => Ok(Word::default()),
+    }
+}
+
+fn lex(input: &str, single_word: bool) -> Result<Vec<SpannedToken>, LexerError> {
     let mut lexer_state = LexerState::Idle;
     let mut current = String::new();
-    let mut tokens: Vec<Token> = Vec::new();
+    let mut segments: Vec<WordSegment> = Vec::new();
+    // Index of the first segment produced by the current double-quoted string.
+    let mut quote_start = 0;
+    let mut tokens: Vec<SpannedToken> = Vec::new();
+    let mut word_start = 0;
+    // Offset of the quote that opened the current quoted string.
+    let mut quote_offset = 0;
+    // Index of the first token on the current line, whose here-document bodies
+    // start after the next newline.
+    let mut line_tokens = 0;
 
-    let mut chars = input.chars().peekable();
+    let mut chars = Cursor::new(input);
 
-    while let Some(ch) = chars.next() {
-        match ch {
-            ' ' => match lexer_state {
-                LexerState::Idle => {}
-                LexerState::ReadingLiteral => {
-                    current.push(' ');
-                }
-                LexerState::ReadingWord => {
-                    tokens.push(Token::Word(current.clone()));
-                    current.clear();
-                    lexer_state = LexerState::Idle;
-                }
-                LexerState::ReadingOtherTokens => {
-                    lexer_state = LexerState::Idle;
-                }
-            },
-            '"' => match lexer_state {
-                LexerState::ReadingLiteral => {
-                    tokens.push(Token::StringLiteral(current.clone()));
-                    current.clear();
-                    lexer_state = LexerState::Idle;
-                }
-                LexerState::Idle => {
-                    lexer_state = LexerState::ReadingLiteral;
-                }
-                LexerState::ReadingOtherTokens => {
-                    lexer_state = LexerState::ReadingLiteral;
-                }
-                _ => {
-                    return Err(LexerError::UnexpectedCharacter(ch));
+    loop {
+        let offset = chars.offset();
+        let Some(ch) = chars.next() else {
+            break;
+        };
+
+        match lexer_state {
+            LexerState::ReadingSingleQuoted => match ch {
+                '\'' => {
+                    segments.push(WordSegment::SingleQuoted(mem::take(&mut current)));
+                    lexer_state = LexerState::ReadingWord;
                 }
+                _ => current.push(ch),
             },
-            '\\' => match lexer_state {
-                LexerState::ReadingLiteral | LexerState::ReadingWord => {
-                    if let Some(escaped_char) = chars.next() {
-                        let char_to_push = match escaped_char {
-                            'n' => '\n',
-                            't' => '\t',
-                            '\\' => '\\',
-                            other => other,
-                        };
-                        current.push(char_to_push);
-                    } else {
-                        return Err(LexerError::IncompleteEscapeSequence);
+            LexerState::ReadingLiteral => match ch {
+                '"' => {
+                    if !current.is_empty() || segments.len() == quote_start {
+                        segments.push(WordSegment::DoubleQuoted(mem::take(&mut current)));
                     }
+                    lexer_state = LexerState::ReadingWord;
                 }
-                LexerState::Idle => {
-                    if let Some(escaped_char) = chars.next() {
-                        if escaped_char.is_whitespace() {
-                        } else {
-                            let char_to_push = match escaped_char {
-                                'n' => '\n',
-                                't' => '\t',
-                                '\\' => '\\',
-                                other => other,
-                            };
-                            current.push(char_to_push);
-                            lexer_state = LexerState::ReadingWord;
+                '$' => match read_expansion(&mut chars, true, offset)? {
+                    Some(segment) => {
+                        if !current.is_empty() {
+                            segments.push(WordSegment::DoubleQuoted(mem::take(&mut current)));
                         }
-                    } else {
-                        return Err(LexerError::IncompleteEscapeSequence);
+                        segments.push(segment);
                     }
+                    None => current.push(ch),
+                },
+                '`' => {
+                    if !current.is_empty() {
+                        segments.push(WordSegment::DoubleQuoted(mem::take(&mut current)));
+                    }
+                    segments.push(read_backquoted(&mut chars, true, offset)?);
                 }
-                _ => {
-                    return Err(LexerError::UnexpectedCharacter(ch));
-                }
-            },
-            '>' | '&' | '|' => match lexer_state {
-                LexerState::Idle => {
-                    if let Some(&char) = chars.peek()
-                        && char == ch
-                    {
-                        chars.next();
-                        let token = match ch {
-                            '>' => Token::RedirectAppend,
-                            '&' => Token::AndIf,
-                            '|' => Token::OrIf,
-                            _ => unreachable!(),
-                        };
-                        tokens.push(token)
-                    } else {
-                        let token = match ch {
-                            '>' => Token::RedirectOut,
-                            '&' => Token::Background,
-                            '|' => Token::PIPE,
-                            _ => unreachable!(),
-                        };
-                        tokens.push(token)
+                // Only characters special inside double quotes can be escaped;
+                // any other backslash is kept.
+                '\\' => match chars.next() {
+                    Some('\n') => {}
+                    Some(escaped_char @ ('$' | '`' | '"' | '\\')) => current.push(escaped_char),
+                    Some(escaped_char) => {
+                        current.push(ch);
+                        current.push(escaped_char);
                     }
-
-                    lexer_state = LexerState::ReadingOtherTokens;
+                    None => {
+                        return Err(LexerError::IncompleteEscapeSequence(
+                            chars.span_from(offset),
+                        ));
+                    }
+                },
+                _ => current.push(ch),
+            },
+            LexerState::Idle | LexerState::ReadingWord | LexerState::ReadingOtherTokens => {
+                if lexer_state != LexerState::ReadingWord {
+                    word_start = offset;
                 }
-                LexerState::ReadingWord => {
-                    tokens.push(Token::Word(current.clone()));
-                    current.clear();
+                let word_span = |chars: &Cursor| chars.span(word_start, offset);
 
-                    if let Some(&char) = chars.peek()
-                        && char == ch
-                    {
-                        chars.next();
-                        let token = match ch {
-                            '>' => Token::RedirectAppend,
-                            '&' => Token::AndIf,
-                            '|' => Token::OrIf,
-                            _ => unreachable!(),
+                match ch {
+                    ' ' | '\t' if !single_word => {
+                        if lexer_state == LexerState::ReadingWord {
+                            finish_word(
+                                &mut current,
+                                &mut segments,
+                                &mut tokens,
+                                word_span(&chars),
+                            );
+                        }
+                        lexer_state = LexerState::Idle;
+                    }
+                    '\n' if !single_word => {
+                        if lexer_state == LexerState::ReadingWord {
+                            finish_word(
+                                &mut current,
+                                &mut segments,
+                                &mut tokens,
+                                word_span(&chars),
+                            );
+                        }
+                        tokens.push(SpannedToken {
+                            token: Token::Newline,
+                            span: chars.span(offset, offset + 1),
+                        });
+                        read_here_documents(&mut chars, &mut tokens[line_tokens..])?;
+                        line_tokens = tokens.len();
+                        lexer_state = LexerState::Idle;
+                    }
+                    '\'' => {
+                        flush_unquoted(&mut current, &mut segments);
+                        quote_offset = offset;
+                        lexer_state = LexerState::ReadingSingleQuoted;
+                    }
+                    '"' => {
+                        flush_unquoted(&mut current, &mut segments);
+                        quote_start = segments.len();
+                        quote_offset = offset;
+                        lexer_state = LexerState::ReadingLiteral;
+                    }
+                    '$' => {
+                        match read_expansion(&mut chars, false, offset)? {
+                            Some(segment) => {
+                                flush_unquoted(&mut current, &mut segments);
+                                segments.push(segment);
+                            }
+                            None => current.push(ch),
+                        }
+                        lexer_state = LexerState::ReadingWord;
+                    }
+                    '`' => {
+                        flush_unquoted(&mut current, &mut segments);
+                        segments.push(read_backquoted(&mut chars, false, offset)?);
+                        lexer_state = LexerState::ReadingWord;
+                    }
+                    '\\' => match chars.next() {
+                        // A backslash-newline joins the two lines.
+                        Some('\n') => {}
+                        Some(escaped_char) => {
+                            // An escaped character is quoted, so it is kept as
+                            // its own single-quoted segment.
+                            flush_unquoted(&mut current, &mut segments);
+                            segments.push(WordSegment::SingleQuoted(escaped_char.to_string()));
+                            lexer_state = LexerState::ReadingWord;
+                        }
+                        None => {
+                            return Err(LexerError::IncompleteEscapeSequence(
+                                chars.span_from(offset),
+                            ));
+                        }
+                    },
+                    '>' | '&' | '|' | '<' | ';' | '(' | ')' if !single_word => {
+                        if lexer_state == LexerState::ReadingWord {
+                            // Unquoted digits right before `<` or `>` name the
+                            // file descriptor to redirect.
+                            let io_number = if matches!(ch, '<' | '>') && segments.is_empty() {
+                                io_number(&current)
+                            } else {
+                                None
+                            };
+                            match io_number {
+                                Some(fd) => {
+                                    current.clear();
+                                    tokens.push(SpannedToken {
+                                        token: Token::IoNumber(fd),
+                                        span: word_span(&chars),
+                                    });
+                                }
+                                None => finish_word(
+                                    &mut current,
+                                    &mut segments,
+                                    &mut tokens,
+                                    word_span(&chars),
+                                ),
+                            }
+                        }
+
+                        // Consumes the next character if it is `expected`.
+                        let mut next_is = |expected: char| {
+                            let found = chars.peek() == Some(&expected);
+                            if found {
+                                chars.next();
+                            }
+                            found
                         };
-                        tokens.push(token)
-                    } else {
                         let token = match ch {
+                            '>' if next_is('>') => Token::RedirectAppend,
+                            '>' if next_is('|') => Token::RedirectClobber,
+                            '>' if next_is('&') => Token::DuplicateOut,
                             '>' => Token::RedirectOut,
+                            '<' if next_is('<') => {
+                                if next_is('<') {
+                                    Token::HereString
+                                } else {
+                                    Token::HereDocument {
+                                        strip_tabs: next_is('-'),
+                                    }
+                                }
+                            }
+                            '<' if next_is('>') => Token::RedirectReadWrite,
+                            '<' if next_is('&') => Token::DuplicateIn,
+                            '<' => Token::RedirectIn,
+                            '&' if next_is('&') => Token::AndIf,
                             '&' => Token::Background,
+                            '|' if next_is('|') => Token::OrIf,
                             '|' => Token::PIPE,
+                            ';' if next_is(';') => {
+                                if next_is('&') {
+                                    Token::DoubleSemicolonAnd
+                                } else {
+                                    Token::DoubleSemicolon
+                                }
+                            }
+                            ';' if next_is('&') => Token::SemicolonAnd,
+                            ';' => Token::Semicolon,
+                            '(' => Token::LeftParen,
+                            ')' => Token::RightParen,
                             _ => unreachable!(),
                         };
-                        tokens.push(token)
+                        let end = chars.offset();
+                        tokens.push(SpannedToken {
+                            token,
+                            span: chars.span(offset, end),
+                        });
+
+                        lexer_state = LexerState::ReadingOtherTokens;
                     }
+                    // A `#` starting a word comments out the rest of the line.
+                    '#' if lexer_state != LexerState::ReadingWord && !single_word => {
+                        while chars.peek().is_some_and(|&next| next != '\n') {
+                            chars.next();
+                        }
+                    }
+                    _ => {
+                        current.push(ch);
+                        lexer_state = LexerState::ReadingWord;
+                    }
+                }
+            }
+        }
+    }
+
+    let end = input.len();
+
+    match lexer_state {
+        LexerState::ReadingWord => {
+            let word_span = chars.span(word_start, end);
+            finish_word(&mut current, &mut segments, &mut tokens, word_span);
+        }
+        LexerState::ReadingLiteral | LexerState::ReadingSingleQuoted => {
+            return Err(LexerError::UnterminatedStringLiteral(
+                chars.span_from(quote_offset),
+            ));
+        }
+        _ => {}
+    }
+
+    // Input ending on the line of a `<<` leaves no room for its body.
+    if let Some(index) = here_documents(&tokens[line_tokens..]).next() {
+        let span = tokens[line_tokens + index].span;
+        return Err(LexerError::UnterminatedHereDocument(
+            chars.span_from(span.start),
+        ));
+    }
+
+    tokens.push(SpannedToken {
+        token: Token::EOF,
+        span: chars.span(end, end),
+    });
+
+    Ok(tokens)
+}
+
+/// Indices of the `<<` operators in `tokens` that are followed by their
+/// delimiter word.
+fn here_documents(tokens: &[SpannedToken]) -> impl Iterator<Item = usize> + '_ {
+    tokens.windows(2).enumerate().filter_map(|(index, pair)| {
+        match (&pair[0].token, &pair[1].token) {
+            (Token::HereDocument { .. }, Token::Word(_)) => Some(index),
+            _ => None,
+        }
+    })
+}
+
+/// Reads the bodies of the here-documents among `tokens`, which start right
+/// after the newline just lexed, and replaces each delimiter word with the
+/// body.
+fn read_here_documents(chars: &mut Cursor, tokens: &mut [SpannedToken]) -> Result<(), LexerError> {
+    let indices: Vec<usize> = here_documents(tokens).collect();
+
+    for index in indices {
+        let Token::HereDocument { strip_tabs } = tokens[index].token else {
+            unreachable!();
+        };
+        let Token::Word(delimiter) = &tokens[index + 1].token else {
+            unreachable!();
+        };
+        let body = read_here_document(chars, delimiter, strip_tabs, tokens[index].span.start)?;
+        tokens[index + 1].token = Token::Word(body);
+    }
+    Ok(())
+}
 
-                    lexer_state = LexerState::ReadingOtherTokens;
+/// Reads the lines up to the one matching `delimiter` and returns them as a
+/// word. With an unquoted delimiter the body goes through parameter, command
+/// and arithmetic expansion, with a backslash only quoting `$`, `` ` ``, `\`
+/// and newline; otherwise it is taken literally.
+fn read_here_document(
+    chars: &mut Cursor,
+    delimiter: &Word,
+    strip_tabs: bool,
+    operator_start: usize,
+) -> Result<Word, LexerError> {
+    let quoted = delimiter
+        .segments
+        .iter()
+        .any(|segment| !matches!(segment, WordSegment::Unquoted(_)));
+    let delimiter: String = delimiter
+        .segments
+        .iter()
+        .map(|segment| match segment {
+            WordSegment::Unquoted(text)
+            | WordSegment::SingleQuoted(text)
+            | WordSegment::DoubleQuoted(text) => text.clone(),
+            other => other.to_string(),
+        })
+        .collect();
+
+    let input = chars.input;
+    let start = chars.offset();
+    let mut line_start = start;
+    let (body_end, delimiter_end) = loop {
+        let line_end = input[line_start..]
+            .find('\n')
+            .map_or(input.len(), |index| line_start + index);
+        let line = &input[line_start..line_end];
+        let line = if strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            line
+        };
+
+        if line == delimiter {
+            break (line_start, line_end);
+        }
+        if line_end == input.len() {
+            return Err(LexerError::UnterminatedHereDocument(
+                chars.span_from(operator_start),
+            ));
+        }
+        line_start = line_end + 1;
+    };
+
+    let mut body = Cursor::new(&input[..body_end]);
+    body.skip_to(start);
+    let span = body.span(start, body_end);
+
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut at_line_start = true;
+
+    loop {
+        let offset = body.offset();
+        let Some(ch) = body.next() else {
+            break;
+        };
+        if at_line_start && strip_tabs && ch == '\t' {
+            continue;
+        }
+        at_line_start = ch == '\n';
+
+        match ch {
+            _ if quoted => current.push(ch),
+            '$' => match read_expansion(&mut body, true, offset)? {
+                Some(segment) => {
+                    if !current.is_empty() {
+                        segments.push(WordSegment::DoubleQuoted(mem::take(&mut current)));
+                    }
+                    segments.push(segment);
+                }
+                None => current.push(ch),
+            },
+            '`' => {
+                if !current.is_empty() {
+                    segments.push(WordSegment::DoubleQuoted(mem::take(&mut current)));
+                }
+                segments.push(read_backquoted(&mut body, true, offset)?);
+            }
+            '\\' => match body.peek() {
+                Some('\n') => {
+                    body.next();
                 }
-                _ => return Err(LexerError::UnexpectedCharacter(ch)),
+                Some(&escaped_char @ ('$' | '`' | '\\')) => {
+                    body.next();
+                    current.push(escaped_char);
+                }
+                _ => current.push(ch),
             },
-            '<' | ';' => match lexer_state {
-                LexerState::Idle => {
-                    let token = match ch {
-                        '<' => Token::RedirectIn,
-                        ';' => Token::Semicolon,
-                        _ => unreachable!(),
-                    };
+            _ => current.push(ch),
+        }
+    }
+    if !current.is_empty() || segments.is_empty() {
+        segments.push(WordSegment::DoubleQuoted(current));
+    }
 
-                    tokens.push(token);
+    chars.skip_to(delimiter_end);
+    // The newline after the delimiter ends the here-document, not a command.
+    if chars.peek() == Some(&'\n') {
+        chars.next();
+    }
 
-                    lexer_state = LexerState::ReadingOtherTokens;
+    Ok(Word { segments, span })
+}
+
+/// Reads the expansion following a `$`, returning `None` when the `$` does
+/// not start an expansion and should be kept literally.
+fn read_expansion(
+    chars: &mut Cursor,
+    quoted: bool,
+    start: usize,
+) -> Result<Option<WordSegment>, LexerError> {
+    let mut expression = String::new();
+
+    match chars.peek() {
+        Some('(') => {
+            chars.next();
+            if chars.peek() == Some(&'(') {
+                chars.next();
+                let expression = read_arithmetic(chars, start)?;
+                return Ok(Some(WordSegment::Arithmetic { expression, quoted }));
+            }
+            let command = read_command_substitution(chars, start)?;
+            return Ok(Some(WordSegment::CommandSubstitution { command, quoted }));
+        }
+        Some('{') => {
+            chars.next();
+            let mut depth = 0;
+            let mut quote = None;
+            loop {
+                let ch = chars.next().ok_or_else(|| {
+                    LexerError::UnterminatedParameterExpansion(chars.span_from(start))
+                })?;
+                match (ch, quote) {
+                    ('\\', _) if quote != Some('\'') => {
+                        expression.push(ch);
+                        if let Some(escaped_char) = chars.next() {
+                            expression.push(escaped_char);
+                        }
+                        continue;
+                    }
+                    ('\'' | '"', None) => quote = Some(ch),
+                    (_, Some(open)) if ch == open => quote = None,
+                    ('{', None) => depth += 1,
+                    ('}', None) if depth == 0 => break,
+                    ('}', None) => depth -= 1,
+                    _ => {}
                 }
-                LexerState::ReadingWord => {
-                    tokens.push(Token::Word(current.clone()));
-                    current.clear();
-                    let token = match ch {
-                        '<' => Token::RedirectIn,
-                        ';' => Token::Semicolon,
-                        _ => unreachable!(),
-                    };
-
-                    tokens.push(token);
-
-                    lexer_state = LexerState::ReadingOtherTokens;
+                expression.push(ch);
+            }
+        }
+        Some(&ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            while let Some(&ch) = chars.peek() {
+                if !(ch.is_ascii_alphanumeric() || ch == '_') {
+                    break;
                 }
-                _ => return Err(LexerError::UnexpectedCharacter(ch)),
-            },
-            _ => match lexer_state {
-                LexerState::Idle => {
-                    current.push(ch);
-                    lexer_state = LexerState::ReadingWord;
+                expression.push(ch);
+                chars.next();
+            }
+        }
+        Some(&ch) if ch.is_ascii_digit() || "?$!#@*-".contains(ch) => {
+            expression.push(ch);
+            chars.next();
+        }
+        _ => return Ok(None),
+    }
+
+    Ok(Some(WordSegment::Parameter { expression, quoted }))
+}
+
+/// Reads the body of a `$(...)` up to its matching parenthesis. A `)` that
+/// leaves the body incomplete, like the one after a `case` pattern, is part
+/// of it.
+fn read_command_substitution(chars: &mut Cursor, start: usize) -> Result<String, LexerError> {
+    let unterminated =
+        |chars: &Cursor| LexerError::UnterminatedCommandSubstitution(chars.span_from(start));
+    let mut command = String::new();
+    let mut depth = 0;
+
+    loop {
+        let ch = chars.next().ok_or_else(|| unterminated(chars))?;
+        match ch {
+            '\\' => {
+                command.push(ch);
+                if let Some(escaped_char) = chars.next() {
+                    command.push(escaped_char);
                 }
-                LexerState::ReadingLiteral => current.push(ch),
-                LexerState::ReadingWord => current.push(ch),
-                _ => return Err(LexerError::UnexpectedCharacter(ch)),
-            },
+                continue;
+            }
+            '\'' | '"' => {
+                command.push(ch);
+                loop {
+                    let quoted_char = chars.next().ok_or_else(|| unterminated(chars))?;
+                    command.push(quoted_char);
+                    if quoted_char == ch {
+                        break;
+                    }
+                    if quoted_char == '\\'
+                        && ch == '"'
+                        && let Some(escaped_char) = chars.next()
+                    {
+                        command.push(escaped_char);
+                    }
+                }
+                continue;
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 && !is_incomplete(&command) => break,
+            ')' if depth > 0 => depth -= 1,
+            _ => {}
         }
+        command.push(ch);
     }
 
-    match lexer_state {
-        LexerState::ReadingWord => {
-            tokens.push(Token::Word(current.clone()));
-            current.clear();
+    Ok(command)
+}
+
+/// Returns whether `command` ends inside a construct that a `)` can continue,
+/// such as a here-document or the pattern list of a `case` item. A missing
+/// command after an operator does not count, so that `$(a |)` still ends at
+/// its `)`.
+fn is_incomplete(command: &str) -> bool {
+    match lex(command.trim_end(), false) {
+        Ok(tokens) => {
+            !parser::is_empty(&tokens)
+                && matches!(
+                    parser::parse(tokens),
+                    Err(ParserError::UnexpectedToken {
+                        token: Token::EOF,
+                        ..
+                    })
+                )
+        }
+        Err(error) => error.is_incomplete(),
+    }
+}
+
+/// Reads the body of a `$((...))` up to the matching `))`.
+fn read_arithmetic(chars: &mut Cursor, start: usize) -> Result<String, LexerError> {
+    let unterminated =
+        |chars: &Cursor| LexerError::UnterminatedArithmeticExpansion(chars.span_from(start));
+    let mut expression = String::new();
+    let mut depth = 0;
+
+    loop {
+        let ch = chars.next().ok_or_else(|| unterminated(chars))?;
+        match ch {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                let offset = chars.offset();
+                match chars.next() {
+                    Some(')') => break,
+                    Some(ch) => {
+                        let end = chars.offset();
+                        return Err(LexerError::UnexpectedCharacter(ch, chars.span(offset, end)));
+                    }
+                    None => return Err(unterminated(chars)),
+                }
+            }
+            ')' => depth -= 1,
+            _ => {}
         }
-        LexerState::ReadingLiteral => {
-            return Err(LexerError::UnterminatedStringLiteral);
+        expression.push(ch);
+    }
+
+    Ok(expression)
+}
+
+/// Reads a legacy `` `...` `` command substitution after its opening
+/// backquote. A backslash only quotes `$`, `` ` `` and `\\` (and `"` inside
+/// double quotes); any other backslash is kept.
+fn read_backquoted(
+    chars: &mut Cursor,
+    quoted: bool,
+    start: usize,
+) -> Result<WordSegment, LexerError> {
+    let unterminated =
+        |chars: &Cursor| LexerError::UnterminatedCommandSubstitution(chars.span_from(start));
+    let mut command = String::new();
+
+    loop {
+        let ch = chars.next().ok_or_else(|| unterminated(chars))?;
+        match ch {
+            '`' => break,
+            '\\' => match chars.next() {
+                Some(escaped_char @ ('$' | '`' | '\\')) => command.push(escaped_char),
+                Some('"') if quoted => command.push('"'),
+                Some(escaped_char) => {
+                    command.push(ch);
+                    command.push(escaped_char);
+                }
+                None => return Err(unterminated(chars)),
+            },
+            _ => command.push(ch),
         }
-        // LexerState::ReadingOtherTokens => {
-        //     return Err(LexerError::IncompleteSpecialToken);
-        // }
-        _ => {}
     }
 
-    tokens.push(Token::EOF);
+    Ok(WordSegment::CommandSubstitution { command, quoted })
+}
+
+fn io_number(digits: &str) -> Option<i32> {
+    if !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn flush_unquoted(current: &mut String, segments: &mut Vec<WordSegment>) {
+    if !current.is_empty() {
+        segments.push(WordSegment::Unquoted(mem::take(current)));
+    }
+}
+
+fn finish_word(
+    current: &mut String,
+    segments: &mut Vec<WordSegment>,
+    tokens: &mut Vec<SpannedToken>,
+    span: Span,
+) {
+    flush_unquoted(current, segments);
+    tokens.push(SpannedToken {
+        token: Token::Word(Word {
+            segments: mem::take(segments),
+            span,
+        }),
+        span,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenization(input.to_string())
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    /// The segments of each word in `input`.
+    fn words(input: &str) -> Vec<Vec<WordSegment>> {
+        tokens(input)
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Word(word) => Some(word.segments),
+                _ => None,
+            })
+            .collect()
+    }
 
-    return Ok(tokens);
+    fn single(text: &str) -> WordSegment {
+        WordSegment::SingleQuoted(text.to_string())
+    }
+
+    fn double(text: &str) -> WordSegment {
+        WordSegment::DoubleQuoted(text.to_string())
+    }
+
+    fn unquoted(text: &str) -> WordSegment {
+        WordSegment::Unquoted(text.to_string())
+    }
+
+    #[test]
+    fn single_quotes_are_verbatim() {
+        assert_eq!(words("'a  b'"), [vec![single("a  b")]]);
+        assert_eq!(words(r"'$x \n `y` #'"), [vec![single(r"$x \n `y` #")]]);
+        assert_eq!(words("''"), [vec![single("")]]);
+        assert_eq!(
+            words("a'b'c"),
+            [vec![unquoted("a"), single("b"), unquoted("c")]]
+        );
+    }
+
+    #[test]
+    fn unterminated_single_quote() {
+        assert!(matches!(
+            tokenization("echo 'abc".to_string()),
+            Err(LexerError::UnterminatedStringLiteral(span)) if span.start == 5
+        ));
+    }
+
+    #[test]
+    fn words_are_quoted_and_unquoted_segments() {
+        assert_eq!(
+            words("a\"b c\"d"),
+            [vec![unquoted("a"), double("b c"), unquoted("d")]]
+        );
+        assert_eq!(words("\"\""), [vec![double("")]]);
+        assert_eq!(
+            words("a  b\tc"),
+            [[unquoted("a")], [unquoted("b")], [unquoted("c")]]
+        );
+    }
+
+    #[test]
+    fn backslashes_in_double_quotes() {
+        assert_eq!(words(r#""\$ \` \" \\""#), [vec![double(r#"$ ` " \"#)]]);
+        // Other backslashes are kept.
+        assert_eq!(
+            words(r#""C:\temp a\.b \n""#),
+            [vec![double(r"C:\temp a\.b \n")]]
+        );
+        assert_eq!(words("\"a\\\nb\""), [vec![double("ab")]]);
+    }
+
+    #[test]
+    fn backslashes_outside_quotes() {
+        assert_eq!(
+            words(r"a\nb"),
+            [vec![unquoted("a"), single("n"), unquoted("b")]]
+        );
+        assert_eq!(words(r"\$x"), [vec![single("$"), unquoted("x")]]);
+        assert_eq!(words("a\\\nb"), [vec![unquoted("ab")]]);
+        // An escaped blank is part of a word, even at its start.
+        assert_eq!(
+            words(r"a \ b"),
+            [vec![unquoted("a")], vec![single(" "), unquoted("b")]]
+        );
+        assert_eq!(words(r"\  b"), [vec![single(" ")], vec![unquoted("b")]]);
+    }
+
+    #[test]
+    fn comments_start_words() {
+        assert_eq!(words("echo a # b c"), [[unquoted("echo")], [unquoted("a")]]);
+        assert_eq!(
+            words("echo a#b '#c'"),
+            [
+                vec![unquoted("echo")],
+                vec![unquoted("a#b")],
+                vec![single("#c")]
+            ]
+        );
+        assert!(matches!(
+            tokens("# only\necho").as_slice(),
+            [Token::Newline, Token::Word(_), Token::EOF]
+        ));
+    }
+
+    #[test]
+    fn io_numbers_and_redirection_operators() {
+        assert!(matches!(
+            tokens("2>&1 <>f >|g 3<&- a2>b").as_slice(),
+            [
+                Token::IoNumber(2),
+                Token::DuplicateOut,
+                Token::Word(_),
+                Token::RedirectReadWrite,
+                Token::Word(_),
+                Token::RedirectClobber,
+                Token::Word(_),
+                Token::IoNumber(3),
+                Token::DuplicateIn,
+                Token::Word(_),
+                Token::Word(_),
+                Token::RedirectOut,
+                Token::Word(_),
+                Token::EOF,
+            ]
+        ));
+        // Only unquoted digits right before the operator name a descriptor.
+        assert_eq!(
+            words("'2'>f 2 >f"),
+            [
+                [single("2")],
+                [unquoted("f")],
+                [unquoted("2")],
+                [unquoted("f")]
+            ]
+        );
+    }
+
+    #[test]
+    fn here_document_bodies() {
+        let parameter = WordSegment::Parameter {
+            expression: "x".to_string(),
+            quoted: true,
+        };
+        assert_eq!(
+            words("cat <<E\nhi $x\nE\n"),
+            [
+                vec![unquoted("cat")],
+                vec![double("hi "), parameter, double("\n")]
+            ]
+        );
+        // A quoted delimiter turns off expansions in the body.
+        assert_eq!(
+            words("cat <<'E'\nhi $x\nE\n"),
+            [vec![unquoted("cat")], vec![double("hi $x\n")]]
+        );
+        // `<<-` strips leading tabs, including before the delimiter.
+        assert_eq!(
+            words("cat <<-E\n\thi\n\tE\necho"),
+            [[unquoted("cat")], [double("hi\n")], [unquoted("echo")]]
+        );
+        assert!(matches!(
+            tokens("cat <<<w").as_slice(),
+            [
+                Token::Word(_),
+                Token::HereString,
+                Token::Word(_),
+                Token::EOF
+            ]
+        ));
+    }
+
+    #[test]
+    fn unterminated_here_document() {
+        assert!(matches!(
+            tokenization("cat <<E".to_string()),
+            Err(LexerError::UnterminatedHereDocument(span)) if span.start == 4
+        ));
+        assert!(matches!(
+            tokenization("cat <<E\nhi".to_string()),
+            Err(LexerError::UnterminatedHereDocument(_))
+        ));
+    }
+
+    #[test]
+    fn command_substitution_ends_at_its_parenthesis() {
+        let command = |text: &str| WordSegment::CommandSubstitution {
+            command: text.to_string(),
+            quoted: false,
+        };
+        assert_eq!(
+            words("$(echo (a)) b"),
+            [vec![command("echo (a)")], vec![unquoted("b")]]
+        );
+        assert_eq!(words("$(echo ')')"), [vec![command("echo ')'")]]);
+        // The `)` after a case pattern is part of the command.
+        assert_eq!(
+            words("$(case x in x) echo hi;; esac)"),
+            [vec![command("case x in x) echo hi;; esac")]]
+        );
+        assert_eq!(words("$(a |)"), [vec![command("a |")]]);
+    }
 }
@@ -4,4 +4,12 @@ pub mod parser;
 
 pub mod executor;
 
+pub mod expansion;
+
+pub mod arithmetic;
+
+pub mod pattern;
+
+pub mod diagnostic;
+
 pub mod utils;
@@ -1,79 +1,282 @@
 use std::{
-    collections::HashMap,
-    io::{self, Write, stdin},
+    env, fs,
+    io::{self, ErrorKind, Write},
     process,
 };
 
-use flash::{executor, lexer, parser, utils};
+use flash::{
+    diagnostic, executor, lexer, parser,
+    utils::{self, BuiltIns, Command, ExecutionContext, ShellError},
+};
+
+/// Where the shell reads its commands from.
+enum Input {
+    /// `flash script [args...]`
+    Script(String),
+    /// `flash -c string [name [args...]]`
+    CommandString(String),
+    /// `flash [-s] [args...]`: a terminal or a pipe.
+    Stdin,
+}
+
+/// Debugging switches given on the command line.
+#[derive(Default)]
+struct Options {
+    /// `--dump-tokens`: print the tokens of every command line.
+    dump_tokens: bool,
+    /// `--dump-ast`: print the parsed command of every command line.
+    dump_ast: bool,
+}
 
 fn main() -> Result<(), ()> {
-    unsafe {
-        if libc::isatty(libc::STDIN_FILENO) == 1 {
+    let built_ins: BuiltIns = utils::built_ins();
+    let mut args = env::args().peekable();
+    let mut shell_name = args.next().unwrap_or_else(|| "flash".to_string());
+
+    let mut options = Options::default();
+    let mut command_string = None;
+    let mut read_stdin = false;
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-') && arg != "-") {
+        match arg.as_str() {
+            "--" => break,
+            "--dump-tokens" => options.dump_tokens = true,
+            "--dump-ast" => options.dump_ast = true,
+            "-c" => match args.next() {
+                Some(command) => command_string = Some(command),
+                None => {
+                    eprintln!("flash: -c: option requires an argument");
+                    process::exit(2);
+                }
+            },
+            "-s" => read_stdin = true,
+            _ => {
+                eprintln!("flash: {arg}: invalid option");
+                process::exit(2);
+            }
+        }
+    }
+
+    // The first operand names the script, or `$0` with `-c`; with `-s` all
+    // operands are positional parameters.
+    let input = if let Some(command) = command_string {
+        if let Some(name) = args.next() {
+            shell_name = name;
+        }
+        Input::CommandString(command)
+    } else if read_stdin {
+        Input::Stdin
+    } else {
+        match args.next() {
+            Some(path) => {
+                shell_name = path.clone();
+                Input::Script(path)
+            }
+            None => Input::Stdin,
+        }
+    };
+
+    let interactive =
+        matches!(input, Input::Stdin) && unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    if interactive {
+        unsafe {
             //handle error
             libc::setpgid(0, 0);
             libc::tcsetpgrp(libc::STDIN_FILENO, process::id() as i32);
-        };
-    };
+        }
+    }
     let shell_pgid = unsafe { libc::getpgid(0) };
     if shell_pgid == -1 {
         return Err(());
     }
 
-    let built_ins: HashMap<
-        String,
-        Box<
-            dyn Fn(
-                utils::SimpleCommand,
-                &mut utils::ExecutionContext,
-            ) -> Result<i32, utils::ExecutionError>,
-        >,
-    > = utils::built_ins();
-    let mut execution_context = utils::ExecutionContext {
-        variables: HashMap::new(),
+    let mut execution_context = ExecutionContext {
+        variables: utils::import_environment(),
         jobs: vec![],
         shell_pgid: shell_pgid as u32,
+        shell_pid: process::id(),
         last_exit_status: 0,
+        last_background_pid: None,
+        shell_name,
+        positional_parameters: args.collect(),
+        last_substitution_status: None,
+        interactive,
+        noclobber: false,
+        loop_depth: 0,
+        loop_control: None,
+        source: String::new(),
     };
 
-    utils::ignore_signals();
+    match input {
+        Input::Script(path) => {
+            let status = run_script(&path, &options, &mut execution_context, &built_ins);
+            process::exit(status);
+        }
+        Input::CommandString(command) => {
+            let status = run_program(&command, &options, &mut execution_context, &built_ins);
+            process::exit(status);
+        }
+        // Piped input is run a command at a time, so that the commands can
+        // read the rest of it.
+        Input::Stdin if !interactive => {}
+        Input::Stdin => utils::ignore_signals(),
+    }
 
     let mut input = String::new();
 
     loop {
-        let mut status = 0;
-        for (idx, pid) in execution_context.jobs.iter_mut().enumerate() {
-            if *pid == 0 || *pid == -1 {
-                continue;
-            }
-            unsafe {
-                match libc::waitpid(*pid, &mut status, libc::WNOHANG) {
-                    0 => {}
-                    -1 => {
-                        *pid = -1;
-                    }
-                    _ => {
-                        println!("[{}] Done {status}", idx + 1);
-                        *pid = 0;
-                    }
-                };
+        if interactive {
+            let mut status = 0;
+            for (idx, pid) in execution_context.jobs.iter_mut().enumerate() {
+                if *pid == 0 || *pid == -1 {
+                    continue;
+                }
+                unsafe {
+                    match libc::waitpid(*pid, &mut status, libc::WNOHANG) {
+                        0 => {}
+                        -1 => {
+                            *pid = -1;
+                        }
+                        _ => {
+                            println!("[{}] Done {status}", idx + 1);
+                            *pid = 0;
+                        }
+                    };
+                }
             }
+
+            print!("$ ");
         }
 
-        input.clear();
-        print!("$ ");
-        io::stdout().flush().unwrap();
-        stdin().read_line(&mut input).map_err(|_| ())?;
-        let tokens = lexer::tokenization(input.clone()).unwrap();
-        println!("{tokens:?}");
-        let command = parser::parse(tokens).unwrap();
-        println!("{command:?}");
-        match executor::execute(&command, &mut execution_context, &built_ins) {
-            Ok(code) => {
-                execution_context.last_exit_status = code;
+        let Some(parsed) = read_command(&mut input, &options, &execution_context) else {
+            process::exit(execution_context.last_exit_status);
+        };
+
+        let result = match parsed {
+            Ok(Some(command)) => {
+                execution_context.source.clone_from(&input);
+                executor::execute(&command, &mut execution_context, &built_ins).map_err(Into::into)
             }
+            Ok(None) => Ok(execution_context.last_exit_status),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(code) => execution_context.last_exit_status = code,
             Err(e) => {
-                eprintln!("{e:?}");
+                report(&input, &e);
+                if !interactive {
+                    process::exit(e.exit_status());
+                }
+                execution_context.last_exit_status = e.exit_status();
             }
-        };
+        }
+    }
+}
+
+/// Reads lines from standard input into `input` until they form a complete
+/// command, printing `$PS2` before each continuation line in an interactive
+/// shell. Returns `None` once the input is exhausted.
+fn read_command(
+    input: &mut String,
+    options: &Options,
+    context: &ExecutionContext,
+) -> Option<Result<Option<Command>, ShellError>> {
+    input.clear();
+
+    loop {
+        io::stdout().flush().unwrap();
+        let pending = !input.is_empty();
+        if !utils::read_line(libc::STDIN_FILENO, input) {
+            return pending.then(|| parse(input, options));
+        }
+
+        match parse(input, options) {
+            Err(e) if e.is_incomplete() => {
+                if context.interactive {
+                    let prompt = context.variable("PS2");
+                    print!("{}", prompt.as_deref().unwrap_or("> "));
+                }
+            }
+            parsed => return Some(parsed),
+        }
+    }
+}
+
+/// Runs the whole file at `path` as one program and returns the status the
+/// shell exits with.
+fn run_script(
+    path: &str,
+    options: &Options,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("flash: {path}: {e}");
+            return if e.kind() == ErrorKind::NotFound {
+                127
+            } else {
+                126
+            };
+        }
+    };
+
+    run_program(&source, options, context, built_ins)
+}
+
+/// Runs `source` as one program, reporting any error, and returns the status
+/// the shell exits with.
+fn run_program(
+    source: &str,
+    options: &Options,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> i32 {
+    match run(source, options, context, built_ins) {
+        Ok(code) => code,
+        Err(e) => {
+            report(source, &e);
+            e.exit_status()
+        }
+    }
+}
+
+/// Lexes, parses and executes `input`, returning its exit status.
+fn run(
+    input: &str,
+    options: &Options,
+    context: &mut ExecutionContext,
+    built_ins: &BuiltIns,
+) -> Result<i32, ShellError> {
+    match parse(input, options)? {
+        Some(command) => {
+            context.source = input.to_string();
+            Ok(executor::execute(&command, context, built_ins)?)
+        }
+        None => Ok(context.last_exit_status),
+    }
+}
+
+/// Lexes and parses `input`, returning `None` when it holds no commands.
+fn parse(input: &str, options: &Options) -> Result<Option<Command>, ShellError> {
+    let tokens = lexer::tokenization(input.to_string())?;
+    if options.dump_tokens {
+        eprintln!("{tokens:?}");
+    }
+    if parser::is_empty(&tokens) {
+        return Ok(None);
+    }
+
+    let command = parser::parse(tokens)?;
+    if options.dump_ast {
+        eprintln!("{command:?}");
+    }
+    Ok(Some(command))
+}
+
+fn report(input: &str, error: &ShellError) {
+    match error.span() {
+        Some(span) => eprintln!("{}", diagnostic::render(input, span, &error.to_string())),
+        None => eprintln!("flash: {error}"),
     }
 }
@@ -1,47 +1,94 @@
-use crate::utils::{Command, ConditionalType, RedirectionType, SimpleCommand, Token, WordSource};
+use crate::utils::{
+    Assignment, CaseItem, CaseTerminator, Command, ConditionalType, ParserError, RedirectionType,
+    SimpleCommand, Span, SpannedToken, Token, Word, WordSegment, is_valid_name,
+};
+
+/// Words that are reserved when they appear unquoted in command position.
+const RESERVED_WORDS: [&str; 12] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "do", "done", "case", "esac",
+];
+
+/// Reserved words that end a list instead of starting a command.
+const LIST_TERMINATORS: [&str; 7] = ["then", "elif", "else", "fi", "do", "done", "esac"];
 
 pub struct Parser {
-    pub tokens: Vec<Token>,
+    pub tokens: Vec<SpannedToken>,
     pub position: usize,
 }
 
 impl Parser {
     pub fn peek(&self) -> &Token {
-        &self.tokens[self.position]
+        &self.tokens[self.position].token
+    }
+
+    /// The span of the next token in the input.
+    pub fn span(&self) -> Span {
+        self.tokens[self.position].span
     }
 
     pub fn advance(&mut self) -> Token {
-        let token = self.tokens[self.position].clone();
+        let token = self.tokens[self.position].token.clone();
         if !self.at_end() {
             self.position += 1;
         }
         token
     }
 
-    pub fn consume_word_or_literal(&mut self) -> Option<(String, WordSource)> {
+    pub fn consume_word(&mut self) -> Option<Word> {
         match self.peek().clone() {
             Token::Word(word) => {
                 self.advance();
-                return Some((word, WordSource::Unquoted));
+                Some(word)
             }
-            Token::StringLiteral(literal) => {
-                self.advance();
-                return Some((literal, WordSource::Quoted));
+            _ => None,
+        }
+    }
+
+    /// The next token if it is an unquoted reserved word.
+    pub fn reserved_word(&self) -> Option<&'static str> {
+        let Token::Word(word) = self.peek() else {
+            return None;
+        };
+        match word.segments.as_slice() {
+            [WordSegment::Unquoted(text)] => {
+                RESERVED_WORDS.into_iter().find(|reserved| reserved == text)
             }
             _ => None,
         }
     }
 
+    /// Consumes the reserved word `expected` or fails on whatever is there.
+    fn expect_reserved_word(&mut self, expected: &str) -> Result<(), ParserError> {
+        if self.reserved_word() != Some(expected) {
+            return Err(self.unexpected_token());
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// Returns whether the next token can start another command of a list.
+    fn starts_command(&self) -> bool {
+        matches!(self.peek(), Token::Word(_))
+            && !self
+                .reserved_word()
+                .is_some_and(|word| LIST_TERMINATORS.contains(&word))
+    }
+
     pub fn is_redirection(&self) -> bool {
         let peeked = self.peek();
-        if *peeked == Token::RedirectAppend
-            || *peeked == Token::RedirectIn
-            || *peeked == Token::RedirectOut
-        {
-            return true;
-        } else {
-            return false;
-        }
+        matches!(
+            peeked,
+            Token::RedirectIn
+                | Token::RedirectOut
+                | Token::RedirectAppend
+                | Token::RedirectClobber
+                | Token::RedirectReadWrite
+                | Token::DuplicateIn
+                | Token::DuplicateOut
+                | Token::HereDocument { .. }
+                | Token::HereString
+                | Token::IoNumber(_)
+        )
     }
 
     pub fn is_pipe(&self) -> bool {
@@ -51,66 +98,367 @@ impl Parser {
     fn at_end(&self) -> bool {
         self.position == self.tokens.len() - 1
     }
+
+    pub fn skip_newlines(&mut self) {
+        while *self.peek() == Token::Newline {
+            self.advance();
+        }
+    }
+
+    fn unexpected_token(&self) -> ParserError {
+        ParserError::UnexpectedToken {
+            token: self.peek().clone(),
+            span: self.span(),
+        }
+    }
+}
+
+/// Returns whether `tokens` contain nothing but blank lines.
+pub fn is_empty(tokens: &[SpannedToken]) -> bool {
+    tokens
+        .iter()
+        .all(|spanned| matches!(spanned.token, Token::Newline | Token::EOF))
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Command, ()> {
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<Command, ParserError> {
     let mut parser = Parser {
         tokens,
         position: 0,
     };
-    parse_sequence(&mut parser)
+    let command = parse_sequence(&mut parser)?;
+
+    if !parser.at_end() {
+        return Err(parser.unexpected_token());
+    }
+    Ok(command)
+}
+
+/// Consumes the operator at the current position and parses the command that
+/// must follow it with `parse_operand`.
+fn parse_after_operator(
+    parser: &mut Parser,
+    parse_operand: fn(&mut Parser) -> Result<Command, ParserError>,
+) -> Result<Command, ParserError> {
+    let span = parser.span();
+    let operator = parser.advance();
+
+    // The command may start on the next line.
+    parser.skip_newlines();
+    if *parser.peek() == Token::EOF {
+        return Err(ParserError::MissingCommand {
+            token: operator,
+            span,
+        });
+    }
+    parse_operand(parser)
 }
 
-pub fn parse_simple_command(parser: &mut Parser) -> Result<Command, ()> {
-    let simple_command = match parser.consume_word_or_literal() {
-        Some((cmd, _)) => cmd,
-        None => return Err(()),
+pub fn parse_simple_command(parser: &mut Parser) -> Result<Command, ParserError> {
+    let mut assignments = Vec::new();
+    let mut words = Vec::new();
+
+    while let Some(word) = parser.consume_word() {
+        match split_assignment(&word) {
+            Some(assignment) if words.is_empty() => assignments.push(assignment),
+            _ => words.push(word),
+        }
+    }
+
+    if assignments.is_empty() && words.is_empty() {
+        return Err(parser.unexpected_token());
+    }
+
+    Ok(Command::Simple(SimpleCommand { assignments, words }))
+}
+
+/// Recognizes `name=value`, where `name` and the `=` must be unquoted.
+fn split_assignment(word: &Word) -> Option<Assignment> {
+    let Some(WordSegment::Unquoted(text)) = word.segments.first() else {
+        return None;
     };
+    let (name, value) = text.split_once('=')?;
+
+    if !is_valid_name(name) {
+        return None;
+    }
+
+    let mut segments = word.segments.clone();
+    if value.is_empty() {
+        segments.remove(0);
+    } else {
+        segments[0] = WordSegment::Unquoted(value.to_string());
+    }
+
+    Some(Assignment {
+        name: name.to_string(),
+        value: Word {
+            segments,
+            span: Span {
+                start: word.span.start + name.len() + 1,
+                column: word.span.column + name.len() + 1,
+                ..word.span
+            },
+        },
+    })
+}
+
+/// if := 'if' list 'then' list ('elif' list 'then' list)* ('else' list)? 'fi'
+pub fn parse_if(parser: &mut Parser) -> Result<Command, ParserError> {
+    parser.expect_reserved_word("if")?;
+
+    let mut branches = Vec::new();
+    loop {
+        let condition = parse_sequence(parser)?;
+        parser.expect_reserved_word("then")?;
+        let body = parse_sequence(parser)?;
+        branches.push((condition, body));
+
+        if parser.reserved_word() != Some("elif") {
+            break;
+        }
+        parser.advance();
+    }
+
+    let else_body = if parser.reserved_word() == Some("else") {
+        parser.advance();
+        Some(Box::new(parse_sequence(parser)?))
+    } else {
+        None
+    };
+    parser.expect_reserved_word("fi")?;
+
+    Ok(Command::If {
+        branches,
+        else_body,
+    })
+}
+
+/// while := ('while' | 'until') list do-group
+pub fn parse_loop(parser: &mut Parser) -> Result<Command, ParserError> {
+    let until = parser.reserved_word() == Some("until");
+    parser.advance();
+
+    let condition = Box::new(parse_sequence(parser)?);
+    let body = Box::new(parse_do_group(parser)?);
+
+    Ok(if until {
+        Command::Until { condition, body }
+    } else {
+        Command::While { condition, body }
+    })
+}
+
+/// for := 'for' NAME (linebreak 'in' WORD* (';' | newline))? linebreak do-group
+pub fn parse_for(parser: &mut Parser) -> Result<Command, ParserError> {
+    parser.expect_reserved_word("for")?;
+
+    let var = match parser.peek() {
+        Token::Word(word) => match word.segments.as_slice() {
+            [WordSegment::Unquoted(name)] if is_valid_name(name) => name.clone(),
+            _ => return Err(parser.unexpected_token()),
+        },
+        _ => return Err(parser.unexpected_token()),
+    };
+    let var_span = parser.span();
+    parser.advance();
+    parser.skip_newlines();
+
+    let words = if is_in(parser.peek()) {
+        parser.advance();
+        let mut words = Vec::new();
+        while let Some(word) = parser.consume_word() {
+            words.push(word);
+        }
+        match parser.peek() {
+            Token::Semicolon | Token::Newline => {
+                parser.advance();
+            }
+            _ => return Err(parser.unexpected_token()),
+        }
+        words
+    } else {
+        if *parser.peek() == Token::Semicolon {
+            parser.advance();
+        }
+        vec![Word {
+            segments: vec![WordSegment::Parameter {
+                expression: "@".to_string(),
+                quoted: true,
+            }],
+            span: var_span,
+        }]
+    };
+
+    parser.skip_newlines();
+    let body = Box::new(parse_do_group(parser)?);
+
+    Ok(Command::For { var, words, body })
+}
+
+fn is_in(token: &Token) -> bool {
+    match token {
+        Token::Word(word) => {
+            matches!(word.segments.as_slice(), [WordSegment::Unquoted(text)] if text == "in")
+        }
+        _ => false,
+    }
+}
+
+/// do-group := 'do' list 'done'
+fn parse_do_group(parser: &mut Parser) -> Result<Command, ParserError> {
+    parser.expect_reserved_word("do")?;
+    let body = parse_sequence(parser)?;
+    parser.expect_reserved_word("done")?;
+    Ok(body)
+}
+
+/// case := 'case' WORD linebreak 'in' linebreak case-item* 'esac'
+pub fn parse_case(parser: &mut Parser) -> Result<Command, ParserError> {
+    parser.expect_reserved_word("case")?;
+
+    let Some(word) = parser.consume_word() else {
+        return Err(parser.unexpected_token());
+    };
+    parser.skip_newlines();
+    if !is_in(parser.peek()) {
+        return Err(parser.unexpected_token());
+    }
+    parser.advance();
+
+    let mut items = Vec::new();
+    loop {
+        parser.skip_newlines();
+        if parser.reserved_word() == Some("esac") {
+            break;
+        }
+        items.push(parse_case_item(parser)?);
+    }
+    parser.advance();
+
+    Ok(Command::Case { word, items })
+}
 
-    let mut arguments = vec![simple_command.clone()];
+/// case-item := '('? WORD ('|' WORD)* ')' list? (';;' | ';&' | ';;&')
+///
+/// The terminator may only be left out before the closing `esac`.
+fn parse_case_item(parser: &mut Parser) -> Result<CaseItem, ParserError> {
+    if *parser.peek() == Token::LeftParen {
+        parser.advance();
+    }
 
-    while let Some((arg, _)) = parser.consume_word_or_literal() {
-        arguments.push(arg);
+    let mut patterns = Vec::new();
+    loop {
+        let Some(pattern) = parser.consume_word() else {
+            return Err(parser.unexpected_token());
+        };
+        patterns.push(pattern);
+        if !parser.is_pipe() {
+            break;
+        }
+        parser.advance();
     }
+    if *parser.peek() != Token::RightParen {
+        return Err(parser.unexpected_token());
+    }
+    parser.advance();
+
+    parser.skip_newlines();
+    let body = if parser.starts_command() {
+        Some(parse_sequence(parser)?)
+    } else {
+        None
+    };
+
+    let terminator = match parser.peek() {
+        Token::DoubleSemicolon => CaseTerminator::Break,
+        Token::SemicolonAnd => CaseTerminator::FallThrough,
+        Token::DoubleSemicolonAnd => CaseTerminator::Continue,
+        _ if parser.reserved_word() == Some("esac") => {
+            return Ok(CaseItem {
+                patterns,
+                body,
+                terminator: CaseTerminator::Break,
+            });
+        }
+        _ => return Err(parser.unexpected_token()),
+    };
+    parser.advance();
 
-    Ok(Command::Simple(SimpleCommand {
-        command: simple_command,
-        arguments,
-    }))
+    Ok(CaseItem {
+        patterns,
+        body,
+        terminator,
+    })
 }
 
-pub fn parse_redirect(parser: &mut Parser) -> Result<Command, ()> {
-    let mut child_command = parse_simple_command(parser)?;
+/// Parses a simple or compound command followed by its redirections.
+pub fn parse_redirect(parser: &mut Parser) -> Result<Command, ParserError> {
+    let mut child_command = match parser.reserved_word() {
+        Some("if") => parse_if(parser)?,
+        Some("while" | "until") => parse_loop(parser)?,
+        Some("for") => parse_for(parser)?,
+        Some("case") => parse_case(parser)?,
+        Some(_) => return Err(parser.unexpected_token()),
+        None => parse_simple_command(parser)?,
+    };
+    let mut redirections = Vec::new();
 
     while parser.is_redirection() {
+        let io_number = match *parser.peek() {
+            Token::IoNumber(fd) => {
+                parser.advance();
+                Some(fd)
+            }
+            _ => None,
+        };
+        let span = parser.span();
         let redirection = parser.advance();
 
-        let (filename, _) = parser.consume_word_or_literal().ok_or(())?;
+        let filename = match parser.consume_word() {
+            Some(filename) => filename,
+            None if *parser.peek() == Token::EOF => {
+                return Err(ParserError::MissingRedirectionTarget {
+                    token: redirection,
+                    span,
+                });
+            }
+            None => return Err(parser.unexpected_token()),
+        };
 
         let redirect_type = match redirection {
             Token::RedirectIn => RedirectionType::In,
             Token::RedirectOut => RedirectionType::Out,
             Token::RedirectAppend => RedirectionType::Append,
+            Token::RedirectClobber => RedirectionType::Clobber,
+            Token::RedirectReadWrite => RedirectionType::ReadWrite,
+            Token::DuplicateIn => RedirectionType::DuplicateIn,
+            Token::DuplicateOut => RedirectionType::DuplicateOut,
+            Token::HereDocument { .. } => RedirectionType::HereDocument,
+            Token::HereString => RedirectionType::HereString,
             _ => unreachable!(),
         };
+        let fd = io_number.unwrap_or_else(|| redirect_type.default_fd());
+        redirections.push((redirect_type, fd, filename));
+    }
 
+    // Redirections apply from left to right, so the first one is outermost.
+    for (redirect_type, fd, target_file) in redirections.into_iter().rev() {
         child_command = Command::Redirect {
             child_command: Box::new(child_command),
             redirect_type,
-            target_file: filename,
+            fd,
+            target_file,
         };
     }
 
     Ok(child_command)
 }
 
-pub fn parse_pipe(parser: &mut Parser) -> Result<Command, ()> {
+pub fn parse_pipe(parser: &mut Parser) -> Result<Command, ParserError> {
     let mut left = parse_redirect(parser)?;
 
     while parser.is_pipe() {
-        parser.advance();
-
-        let right = parse_redirect(parser)?;
+        let right = parse_after_operator(parser, parse_redirect)?;
 
         left = Command::Pipe {
             left: Box::new(left),
@@ -121,46 +469,301 @@ pub fn parse_pipe(parser: &mut Parser) -> Result<Command, ()> {
     Ok(left)
 }
 
-pub fn parse_sequence(parser: &mut Parser) -> Result<Command, ()> {
+pub fn parse_and_or(parser: &mut Parser) -> Result<Command, ParserError> {
     let mut command = parse_pipe(parser)?;
 
     loop {
-        match parser.peek().clone() {
-            Token::Semicolon => {
-                parser.advance();
-                let right = parse_pipe(parser)?;
-                command = Command::Sequence {
-                    first: Box::new(command),
-                    second: Box::new(right),
-                };
-            }
+        let operator = match parser.peek() {
+            Token::AndIf => ConditionalType::And,
+            Token::OrIf => ConditionalType::Or,
+            _ => break,
+        };
+        let right = parse_after_operator(parser, parse_pipe)?;
+        command = Command::Conditional {
+            left: Box::new(command),
+            right: Box::new(right),
+            operator,
+        };
+    }
+
+    Ok(command)
+}
+
+/// Parses and-or lists separated by `;`, `&` or newlines. A `&` only puts the
+/// and-or list right before it in the background.
+pub fn parse_sequence(parser: &mut Parser) -> Result<Command, ParserError> {
+    parser.skip_newlines();
+    let mut command: Option<Command> = None;
+
+    loop {
+        let mut item = parse_and_or(parser)?;
+        let separated = match parser.peek() {
             Token::Background => {
-                parser.advance();
-                command = Command::Background {
-                    child_command: Box::new(command),
+                item = Command::Background {
+                    child_command: Box::new(item),
                 };
+                true
             }
-            Token::AndIf => {
-                parser.advance();
-                let right = parse_pipe(parser)?;
-                command = Command::Conditional {
-                    left: Box::new(command),
-                    right: Box::new(right),
-                    operator: ConditionalType::And,
-                }
-            }
-            Token::OrIf => {
-                parser.advance();
-                let right = parse_pipe(parser)?;
-                command = Command::Conditional {
-                    left: Box::new(command),
-                    right: Box::new(right),
-                    operator: ConditionalType::Or,
-                }
-            }
-            _ => break,
+            Token::Semicolon | Token::Newline => true,
+            _ => false,
+        };
+
+        command = Some(match command {
+            Some(first) => Command::Sequence {
+                first: Box::new(first),
+                second: Box::new(item),
+            },
+            None => item,
+        });
+
+        if !separated {
+            break;
+        }
+        parser.advance();
+        parser.skip_newlines();
+        if !parser.starts_command() {
+            break;
         }
     }
 
-    Ok(command)
+    Ok(command.expect("the loop parses at least one command"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn parse_str(input: &str) -> Result<Command, ParserError> {
+        parse(lexer::tokenization(input.to_string()).unwrap())
+    }
+
+    #[test]
+    fn unexpected_tokens() {
+        assert!(matches!(
+            parse_str("; echo"),
+            Err(ParserError::UnexpectedToken { token: Token::Semicolon, span }) if span.start == 0
+        ));
+        assert!(matches!(
+            parse_str("echo )"),
+            Err(ParserError::UnexpectedToken { token: Token::RightParen, span }) if span.start == 5
+        ));
+        assert!(matches!(
+            parse_str("echo > ;"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Semicolon,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_str("fi"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_operands() {
+        assert!(matches!(
+            parse_str("echo a |"),
+            Err(ParserError::MissingCommand { token: Token::PIPE, span }) if span.start == 7
+        ));
+        assert!(matches!(
+            parse_str("echo a &&"),
+            Err(ParserError::MissingCommand {
+                token: Token::AndIf,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_str("echo >"),
+            Err(ParserError::MissingRedirectionTarget { token: Token::RedirectOut, span }) if span.start == 5
+        ));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(parse_str("echo a ||").unwrap_err().is_incomplete());
+        assert!(parse_str("if true; then").unwrap_err().is_incomplete());
+        assert!(!parse_str("echo )").unwrap_err().is_incomplete());
+        assert!(!parse_str("echo >").unwrap_err().is_incomplete());
+    }
+
+    /// The words of a simple command, as written.
+    fn words(command: &Command) -> Vec<String> {
+        match command {
+            Command::Simple(simple) => simple.words.iter().map(Word::to_string).collect(),
+            other => panic!("not a simple command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn if_branches() {
+        let Ok(Command::If {
+            branches,
+            else_body,
+        }) = parse_str("if a; then b; elif c\nthen d; else e; fi")
+        else {
+            panic!("not an if command");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(words(&branches[0].0), ["a"]);
+        assert_eq!(words(&branches[0].1), ["b"]);
+        assert_eq!(words(&branches[1].0), ["c"]);
+        assert_eq!(words(&branches[1].1), ["d"]);
+        assert_eq!(words(&else_body.unwrap()), ["e"]);
+
+        assert!(matches!(
+            parse_str("if a; then b; fi"),
+            Ok(Command::If {
+                else_body: None,
+                ..
+            })
+        ));
+        // Reserved words are only recognized unquoted and in command position.
+        assert!(matches!(
+            parse_str("echo if then fi"),
+            Ok(Command::Simple(_))
+        ));
+        assert!(matches!(parse_str("'if' a"), Ok(Command::Simple(_))));
+    }
+
+    #[test]
+    fn if_errors() {
+        assert!(matches!(
+            parse_str("if a; fi"),
+            Err(ParserError::UnexpectedToken { token: Token::Word(_), span }) if span.start == 6
+        ));
+        assert!(matches!(
+            parse_str("if a; then fi"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn while_and_until_loops() {
+        let Ok(Command::While { condition, body }) = parse_str("while a; do b; c; done") else {
+            panic!("not a while loop");
+        };
+        assert_eq!(words(&condition), ["a"]);
+        assert!(matches!(*body, Command::Sequence { .. }));
+
+        let Ok(Command::Until { condition, body }) = parse_str("until a\ndo\nb\ndone") else {
+            panic!("not an until loop");
+        };
+        assert_eq!(words(&condition), ["a"]);
+        assert_eq!(words(&body), ["b"]);
+
+        assert!(matches!(
+            parse_str("while a; do done"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_str("while a; b; done"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn for_loops() {
+        let Ok(Command::For {
+            var,
+            words: list,
+            body,
+        }) = parse_str("for i in a 'b c'; do echo $i; done")
+        else {
+            panic!("not a for loop");
+        };
+        assert_eq!(var, "i");
+        assert_eq!(
+            list.iter().map(Word::to_string).collect::<Vec<_>>(),
+            ["a", "'b c'"]
+        );
+        assert_eq!(words(&body), ["echo", "${i}"]);
+
+        // Without `in` the loop runs over "$@".
+        let Ok(Command::For { words: list, .. }) = parse_str("for i; do :; done") else {
+            panic!("not a for loop");
+        };
+        assert!(matches!(
+            list[0].segments.as_slice(),
+            [WordSegment::Parameter { expression, quoted: true }] if expression == "@"
+        ));
+
+        let Ok(Command::For { words: list, .. }) = parse_str("for i in\ndo :; done") else {
+            panic!("not a for loop");
+        };
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn for_errors() {
+        assert!(matches!(
+            parse_str("for 1x in a; do :; done"),
+            Err(ParserError::UnexpectedToken { token: Token::Word(_), span }) if span.start == 4
+        ));
+        assert!(matches!(
+            parse_str("for i in a do :; done"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn case_items() {
+        let Ok(Command::Case { word, items }) =
+            parse_str("case $x in\n(a | b) echo 1;;\nc) ;&\n*) echo 2 ;;&\nesac")
+        else {
+            panic!("not a case command");
+        };
+        assert_eq!(word.to_string(), "${x}");
+        assert_eq!(items.len(), 3);
+
+        let patterns: Vec<String> = items[0].patterns.iter().map(Word::to_string).collect();
+        assert_eq!(patterns, ["a", "b"]);
+        assert_eq!(words(items[0].body.as_ref().unwrap()), ["echo", "1"]);
+        assert_eq!(items[0].terminator, CaseTerminator::Break);
+
+        assert!(items[1].body.is_none());
+        assert_eq!(items[1].terminator, CaseTerminator::FallThrough);
+        assert_eq!(items[2].terminator, CaseTerminator::Continue);
+
+        // The last item may leave out its terminator.
+        let Ok(Command::Case { items, .. }) = parse_str("case x in x) echo\nesac") else {
+            panic!("not a case command");
+        };
+        assert_eq!(items[0].terminator, CaseTerminator::Break);
+        assert!(
+            matches!(parse_str("case x in esac"), Ok(Command::Case { items, .. }) if items.is_empty())
+        );
+    }
+
+    #[test]
+    fn case_errors() {
+        assert!(matches!(
+            parse_str("case x a) ;; esac"),
+            Err(ParserError::UnexpectedToken { token: Token::Word(_), span }) if span.start == 7
+        ));
+        assert!(matches!(
+            parse_str("case x in a b) ;; esac"),
+            Err(ParserError::UnexpectedToken {
+                token: Token::Word(_),
+                ..
+            })
+        ));
+        assert!(parse_str("case x in a) echo").unwrap_err().is_incomplete());
+    }
 }
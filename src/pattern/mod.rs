@@ -0,0 +1,331 @@
+use std::fs;
+
+/// A single element of a compiled shell pattern.
+#[derive(Debug, PartialEq)]
+enum PatternToken {
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyString,
+    /// `[...]`
+    Bracket {
+        negated: bool,
+        items: Vec<BracketItem>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    Class(String),
+}
+
+/// Returns whether `text` matches the shell pattern `pattern` as a whole.
+///
+/// `*`, `?` and bracket expressions have their usual meaning and a backslash
+/// makes the next character match literally.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let tokens = compile(pattern);
+    let text: Vec<char> = text.chars().collect();
+
+    // Iterative matching that backtracks to the most recent `*`.
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < tokens.len() && tokens[p] == PatternToken::AnyString {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < tokens.len() && token_matches(&tokens[p], text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    tokens[p..]
+        .iter()
+        .all(|token| *token == PatternToken::AnyString)
+}
+
+/// Returns whether `pattern` contains any unescaped pattern characters.
+pub fn has_wildcards(pattern: &str) -> bool {
+    compile(pattern)
+        .iter()
+        .any(|token| !matches!(token, PatternToken::Literal(_)))
+}
+
+/// Escapes `text` so that it only matches itself when used in a pattern.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Removes the escaping backslashes from a pattern without wildcards.
+pub fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(ch),
+        }
+    }
+    unescaped
+}
+
+/// Returns the sorted paths matching `pattern`, matching each `/` separated
+/// component against directory entries. Hidden files are only matched when the
+/// component starts with a literal `.`.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (mut paths, components) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    for component in components.split('/') {
+        if component.is_empty() {
+            continue;
+        }
+
+        let mut next_paths = Vec::new();
+        for path in &paths {
+            if !has_wildcards(component) {
+                next_paths.push(format!("{path}{}", unescape(component)));
+                continue;
+            }
+
+            let directory = if path.is_empty() { "." } else { path.as_str() };
+            let Ok(entries) = fs::read_dir(directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if matches(component, &name) {
+                    next_paths.push(format!("{path}{name}"));
+                }
+            }
+        }
+
+        paths = next_paths
+            .into_iter()
+            .map(|path| format!("{path}/"))
+            .collect();
+    }
+
+    let mut matched: Vec<String> = paths
+        .into_iter()
+        .map(|path| {
+            if pattern.ends_with('/') || path == "/" {
+                path
+            } else {
+                path.trim_end_matches('/').to_string()
+            }
+        })
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .collect();
+    matched.sort();
+    matched
+}
+
+fn token_matches(token: &PatternToken, ch: char) -> bool {
+    match token {
+        PatternToken::Literal(literal) => *literal == ch,
+        PatternToken::AnyChar => true,
+        PatternToken::AnyString => false,
+        PatternToken::Bracket { negated, items } => {
+            let found = items.iter().any(|item| match item {
+                BracketItem::Char(c) => *c == ch,
+                BracketItem::Range(start, end) => (*start..=*end).contains(&ch),
+                BracketItem::Class(class) => class_matches(class, ch),
+            });
+            found != *negated
+        }
+    }
+}
+
+fn class_matches(class: &str, ch: char) -> bool {
+    match class {
+        "alnum" => ch.is_alphanumeric(),
+        "alpha" => ch.is_alphabetic(),
+        "blank" => ch == ' ' || ch == '\t',
+        "cntrl" => ch.is_control(),
+        "digit" => ch.is_ascii_digit(),
+        "graph" => ch.is_ascii_graphic(),
+        "lower" => ch.is_lowercase(),
+        "print" => ch.is_ascii_graphic() || ch == ' ',
+        "punct" => ch.is_ascii_punctuation(),
+        "space" => ch.is_whitespace(),
+        "upper" => ch.is_uppercase(),
+        "xdigit" => ch.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+fn compile(pattern: &str) -> Vec<PatternToken> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        match chars[idx] {
+            '\\' if idx + 1 < chars.len() => {
+                tokens.push(PatternToken::Literal(chars[idx + 1]));
+                idx += 2;
+                continue;
+            }
+            '*' => {
+                // Consecutive stars are equivalent to a single one.
+                if tokens.last() != Some(&PatternToken::AnyString) {
+                    tokens.push(PatternToken::AnyString);
+                }
+            }
+            '?' => tokens.push(PatternToken::AnyChar),
+            '[' => {
+                if let Some((token, next)) = compile_bracket(&chars, idx) {
+                    tokens.push(token);
+                    idx = next;
+                    continue;
+                }
+                // An unterminated bracket matches a literal `[`.
+                tokens.push(PatternToken::Literal('['));
+            }
+            ch => tokens.push(PatternToken::Literal(ch)),
+        }
+        idx += 1;
+    }
+
+    tokens
+}
+
+/// Compiles the bracket expression starting at `chars[start]`, returning the
+/// token and the index just past the closing `]`.
+fn compile_bracket(chars: &[char], start: usize) -> Option<(PatternToken, usize)> {
+    let mut idx = start + 1;
+    let negated = matches!(chars.get(idx), Some('!' | '^'));
+    if negated {
+        idx += 1;
+    }
+
+    let mut items = Vec::new();
+    let first = idx;
+
+    loop {
+        let ch = *chars.get(idx)?;
+        match ch {
+            ']' if idx > first => return Some((PatternToken::Bracket { negated, items }, idx + 1)),
+            '[' if chars.get(idx + 1) == Some(&':') => {
+                let rest: String = chars[idx + 2..].iter().collect();
+                let end = rest.find(":]")?;
+                items.push(BracketItem::Class(rest[..end].to_string()));
+                idx += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+            _ => {
+                let (ch, width) = match ch {
+                    '\\' => (*chars.get(idx + 1)?, 2),
+                    _ => (ch, 1),
+                };
+                idx += width;
+
+                if chars.get(idx) == Some(&'-') && chars.get(idx + 1).is_some_and(|&c| c != ']') {
+                    let end = match chars[idx + 1] {
+                        '\\' => *chars.get(idx + 2)?,
+                        c => c,
+                    };
+                    idx += if chars[idx + 1] == '\\' { 3 } else { 2 };
+                    items.push(BracketItem::Range(ch, end));
+                } else {
+                    items.push(BracketItem::Char(ch));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.log", "a.b.log"));
+        assert!(!matches("*.log", "a.log.gz"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("*", ""));
+        assert!(matches("?", "é"));
+        assert!(!matches("?", ""));
+        assert!(!matches("a?", "a"));
+    }
+
+    #[test]
+    fn bracket_ranges() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[0-9][0-9]", "42"));
+        assert!(matches("[ac-e]", "d"));
+        // A `-` at either end is literal.
+        assert!(matches("[-a]", "-"));
+        assert!(matches("[a-]", "-"));
+        // A `]` right after the opening bracket is literal.
+        assert!(matches("[]a]", "]"));
+    }
+
+    #[test]
+    fn bracket_classes() {
+        assert!(matches("[[:digit:]]*", "7up"));
+        assert!(!matches("[[:alpha:]]", "1"));
+        assert!(matches("[[:upper:][:digit:]]", "Q"));
+        assert!(matches("[[:upper:][:digit:]]", "3"));
+        assert!(matches("[[:space:]]", "\t"));
+        assert!(!matches("[[:bogus:]]", "a"));
+    }
+
+    #[test]
+    fn bracket_negation() {
+        assert!(matches("[!a]", "b"));
+        assert!(!matches("[!a]", "a"));
+        assert!(matches("[^a-z]", "A"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(!matches("[!a]", ""));
+    }
+
+    #[test]
+    fn escaped_characters() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!matches("a\\?", "ab"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("[a\\-z]", "-"));
+        assert!(!matches("[a\\-z]", "b"));
+        // An unterminated bracket is a literal `[`.
+        assert!(matches("[abc", "[abc"));
+    }
+
+    #[test]
+    fn escape_matches_literally() {
+        let escaped = escape("a*[b]?\\");
+        assert!(matches(&escaped, "a*[b]?\\"));
+        assert!(!matches(&escaped, "axbb?\\"));
+        assert!(!has_wildcards(&escaped));
+        assert_eq!(unescape(&escaped), "a*[b]?\\");
+        assert!(has_wildcards("a[bc]"));
+    }
+}
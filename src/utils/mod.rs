@@ -1,20 +1,96 @@
-use std::{collections::HashMap, env::home_dir, ffi::CString, io::Error, process};
+use std::{
+    collections::HashMap,
+    env::{self, home_dir},
+    ffi::CString,
+    fmt,
+    io::Error,
+    mem, process,
+};
+
+use crate::expansion::{DEFAULT_IFS, is_ifs_whitespace};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Word(String),
-    StringLiteral(String),
+    Word(Word),
     PIPE,
     RedirectIn,
     RedirectOut,
     RedirectAppend,
+    RedirectClobber,
+    RedirectReadWrite,
+    DuplicateIn,
+    DuplicateOut,
+    /// `<<` or `<<-`. Once lexed, the word that follows holds the body.
+    HereDocument {
+        strip_tabs: bool,
+    },
+    /// `<<<`
+    HereString,
+    /// The digits right before a redirection operator, as in `2>`.
+    IoNumber(i32),
     Semicolon,
     Background,
     AndIf,
     OrIf,
+    /// `;;`
+    DoubleSemicolon,
+    /// `;&`
+    SemicolonAnd,
+    /// `;;&`
+    DoubleSemicolonAnd,
+    LeftParen,
+    RightParen,
+    Newline,
     EOF,
 }
 
+/// A byte range in the input, along with the 1-based line and column (in
+/// characters) where it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::PIPE => write!(f, "|"),
+            Token::RedirectIn => write!(f, "<"),
+            Token::RedirectOut => write!(f, ">"),
+            Token::RedirectAppend => write!(f, ">>"),
+            Token::RedirectClobber => write!(f, ">|"),
+            Token::RedirectReadWrite => write!(f, "<>"),
+            Token::DuplicateIn => write!(f, "<&"),
+            Token::DuplicateOut => write!(f, ">&"),
+            Token::HereDocument { strip_tabs: false } => write!(f, "<<"),
+            Token::HereDocument { strip_tabs: true } => write!(f, "<<-"),
+            Token::HereString => write!(f, "<<<"),
+            Token::IoNumber(fd) => write!(f, "{fd}"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Background => write!(f, "&"),
+            Token::AndIf => write!(f, "&&"),
+            Token::OrIf => write!(f, "||"),
+            Token::DoubleSemicolon => write!(f, ";;"),
+            Token::SemicolonAnd => write!(f, ";&"),
+            Token::DoubleSemicolonAnd => write!(f, ";;&"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Newline => write!(f, "newline"),
+            Token::EOF => write!(f, "end of input"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Command {
     Simple(SimpleCommand),
@@ -25,7 +101,9 @@ pub enum Command {
     Redirect {
         child_command: Box<Command>,
         redirect_type: RedirectionType,
-        target_file: String,
+        /// The file descriptor being redirected.
+        fd: i32,
+        target_file: Word,
     },
     Sequence {
         first: Box<Command>,
@@ -39,12 +117,61 @@ pub enum Command {
         right: Box<Command>,
         operator: ConditionalType,
     },
+    If {
+        /// The `if` and `elif` conditions with their bodies, tried in order.
+        branches: Vec<(Command, Command)>,
+        else_body: Option<Box<Command>>,
+    },
+    While {
+        condition: Box<Command>,
+        body: Box<Command>,
+    },
+    Until {
+        condition: Box<Command>,
+        body: Box<Command>,
+    },
+    For {
+        var: String,
+        /// The words after `in`, or `"$@"` when `in` is left out.
+        words: Vec<Word>,
+        body: Box<Command>,
+    },
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+}
+
+/// A `pattern | pattern) list` clause of a `case` command.
+#[derive(Debug)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: Option<Command>,
+    pub terminator: CaseTerminator,
+}
+
+/// What happens after the list of a matching `case` clause has run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    /// `;;` ends the `case` command.
+    Break,
+    /// `;&` runs the next clause's list without testing its patterns.
+    FallThrough,
+    /// `;;&` goes on testing the patterns of the following clauses.
+    Continue,
 }
 
 #[derive(Debug, Clone)]
 pub struct SimpleCommand {
-    pub command: String,
-    pub arguments: Vec<String>,
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+}
+
+/// A `name=value` word preceding a simple command.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
 }
 
 #[derive(Debug)]
@@ -52,6 +179,32 @@ pub enum RedirectionType {
     In,
     Out,
     Append,
+    /// `>|`
+    Clobber,
+    /// `<>`
+    ReadWrite,
+    /// `<&`, duplicating an input file descriptor or closing it with `-`.
+    DuplicateIn,
+    /// `>&`, duplicating an output file descriptor or closing it with `-`.
+    DuplicateOut,
+    /// `<<`, reading from the body held in the target word.
+    HereDocument,
+    /// `<<<`
+    HereString,
+}
+
+impl RedirectionType {
+    /// The file descriptor redirected when no IO number is given.
+    pub fn default_fd(&self) -> i32 {
+        match self {
+            RedirectionType::In
+            | RedirectionType::ReadWrite
+            | RedirectionType::DuplicateIn
+            | RedirectionType::HereDocument
+            | RedirectionType::HereString => 0,
+            _ => 1,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -60,32 +213,245 @@ pub enum ConditionalType {
     Or,
 }
 
-pub enum WordSource {
-    Quoted,
-    Unquoted,
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordSegment {
+    Unquoted(String),
+    DoubleQuoted(String),
+    SingleQuoted(String),
+    /// `$name`, `${...}` or a special parameter; `expression` is the text
+    /// after the `$` without any braces.
+    Parameter {
+        expression: String,
+        quoted: bool,
+    },
+    /// `$(command)` or `` `command` ``.
+    CommandSubstitution {
+        command: String,
+        quoted: bool,
+    },
+    /// `$((expression))`
+    Arithmetic {
+        expression: String,
+        quoted: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub segments: Vec<WordSegment>,
+    pub span: Span,
+}
+
+impl fmt::Display for WordSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WordSegment::Unquoted(text) => write!(f, "{text}"),
+            WordSegment::DoubleQuoted(text) => write!(f, "\"{text}\""),
+            WordSegment::SingleQuoted(text) => write!(f, "'{text}'"),
+            WordSegment::Parameter { expression, .. } => write!(f, "${{{expression}}}"),
+            WordSegment::CommandSubstitution { command, .. } => write!(f, "$({command})"),
+            WordSegment::Arithmetic { expression, .. } => write!(f, "$(({expression}))"),
+        }
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for segment in &self.segments {
+            write!(f, "{segment}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum LexerState {
     Idle,
     ReadingLiteral,
+    ReadingSingleQuoted,
     ReadingWord,
     ReadingOtherTokens,
 }
 
+/// Each variant carries the span of the offending input, from the opening
+/// quote or `$` to the end of input for the unterminated ones.
 #[derive(Debug)]
 pub enum LexerError {
-    UnexpectedCharacter(char),
-    UnterminatedStringLiteral,
-    IncompleteEscapeSequence,
-    IncompleteSpecialToken,
+    UnexpectedCharacter(char, Span),
+    UnterminatedStringLiteral(Span),
+    UnterminatedParameterExpansion(Span),
+    UnterminatedCommandSubstitution(Span),
+    UnterminatedArithmeticExpansion(Span),
+    /// No line matched the delimiter of the here-document at `span`.
+    UnterminatedHereDocument(Span),
+    IncompleteEscapeSequence(Span),
+}
+
+impl LexerError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexerError::UnexpectedCharacter(_, span)
+            | LexerError::UnterminatedStringLiteral(span)
+            | LexerError::UnterminatedParameterExpansion(span)
+            | LexerError::UnterminatedCommandSubstitution(span)
+            | LexerError::UnterminatedArithmeticExpansion(span)
+            | LexerError::UnterminatedHereDocument(span)
+            | LexerError::IncompleteEscapeSequence(span) => *span,
+        }
+    }
+
+    /// Returns whether the input ended inside a construct, so that more
+    /// input could complete it.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            LexerError::UnterminatedStringLiteral(_)
+                | LexerError::UnterminatedParameterExpansion(_)
+                | LexerError::UnterminatedCommandSubstitution(_)
+                | LexerError::UnterminatedArithmeticExpansion(_)
+                | LexerError::UnterminatedHereDocument(_)
+                | LexerError::IncompleteEscapeSequence(_)
+        )
+    }
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexerError::UnexpectedCharacter(ch, _) => write!(f, "unexpected character `{ch}`"),
+            LexerError::UnterminatedStringLiteral(_) => write!(f, "unterminated quoted string"),
+            LexerError::UnterminatedParameterExpansion(_) => {
+                write!(f, "unterminated parameter expansion")
+            }
+            LexerError::UnterminatedCommandSubstitution(_) => {
+                write!(f, "unterminated command substitution")
+            }
+            LexerError::UnterminatedArithmeticExpansion(_) => {
+                write!(f, "unterminated arithmetic expansion")
+            }
+            LexerError::UnterminatedHereDocument(_) => {
+                write!(f, "here-document not terminated by its delimiter")
+            }
+            LexerError::IncompleteEscapeSequence(_) => write!(f, "incomplete escape sequence"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    /// `None` for a name that was exported or made readonly before it was
+    /// given a value.
+    pub value: Option<String>,
+    /// Exported variables are passed to the environment of child processes.
+    pub exported: bool,
+    pub readonly: bool,
 }
 
 pub struct ExecutionContext {
-    pub variables: HashMap<String, String>,
+    pub variables: HashMap<String, Variable>,
     pub jobs: Vec<i32>,
     pub shell_pgid: u32,
+    pub shell_pid: u32,
     pub last_exit_status: i32,
+    pub last_background_pid: Option<i32>,
+    /// `$0`
+    pub shell_name: String,
+    /// `$1`, `$2`, ...
+    pub positional_parameters: Vec<String>,
+    /// Exit status of the most recent command substitution, which becomes the
+    /// status of a command consisting only of assignments.
+    pub last_substitution_status: Option<i32>,
+    /// Whether the shell reads commands from a terminal; job control messages
+    /// are only printed then.
+    pub interactive: bool,
+    /// `set -o noclobber`: `>` refuses to overwrite existing regular files.
+    pub noclobber: bool,
+    /// Number of loops currently executing.
+    pub loop_depth: usize,
+    /// Set by `break` and `continue` to unwind to the enclosing loop.
+    pub loop_control: Option<LoopControl>,
+    /// Text of the command being run, which the spans of its words point into.
+    pub source: String,
+}
+
+/// A pending `break n` or `continue n`, counting the loops still to unwind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopControl {
+    Break(usize),
+    Continue(usize),
+}
+
+impl ExecutionContext {
+    pub fn variable(&self, name: &str) -> Option<String> {
+        self.variables
+            .get(name)
+            .and_then(|variable| variable.value.clone())
+    }
+
+    /// Sets a variable, keeping its exported flag if it already exists.
+    pub fn set_variable(&mut self, name: &str, value: String) -> Result<(), ExecutionError> {
+        match self.variables.get_mut(name) {
+            Some(variable) if variable.readonly => {
+                Err(ExecutionError::ReadonlyVariable(name.to_string()))
+            }
+            Some(variable) => {
+                variable.value = Some(value);
+                Ok(())
+            }
+            None => {
+                self.variables.insert(
+                    name.to_string(),
+                    Variable {
+                        value: Some(value),
+                        exported: false,
+                        readonly: false,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unset_variable(&mut self, name: &str) -> Result<(), ExecutionError> {
+        if self
+            .variables
+            .get(name)
+            .is_some_and(|variable| variable.readonly)
+        {
+            return Err(ExecutionError::ReadonlyVariable(name.to_string()));
+        }
+        self.variables.remove(name);
+        Ok(())
+    }
+
+    /// `name=value` strings for every exported variable, as passed to `execve`.
+    pub fn environment(&self) -> Vec<CString> {
+        self.variables
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| {
+                let value = variable.value.as_ref()?;
+                CString::new(format!("{name}={value}")).ok()
+            })
+            .collect()
+    }
+}
+
+/// Builds the variable table from the environment flash was started with,
+/// marking every variable as exported.
+pub fn import_environment() -> HashMap<String, Variable> {
+    env::vars_os()
+        .map(|(name, value)| {
+            (
+                name.to_string_lossy().into_owned(),
+                Variable {
+                    value: Some(value.to_string_lossy().into_owned()),
+                    exported: true,
+                    readonly: false,
+                },
+            )
+        })
+        .collect()
 }
 
 #[derive(Debug)]
@@ -96,11 +462,179 @@ pub enum ExecutionError {
     NoHomeDirectory,
     InvalidPath,
     DirectoryNotFound,
-    FileError(Error),
+    /// Opening or writing the file at the path failed.
+    FileError(String, Error),
+    PipeError(Error),
+    BadSubstitution(String),
+    InvalidCommandSubstitution(String),
+    ReadonlyVariable(String),
+    InvalidIdentifier(String),
+    /// A `<&` or `>&` target that is not an open file descriptor or `-`.
+    BadFileDescriptor(String),
+    /// A redirection target that did not expand to exactly one field.
+    AmbiguousRedirect(String),
+    /// `>` would overwrite an existing file while `noclobber` is set.
+    FileExists(String),
+    InvalidOption(String),
+    Arithmetic(ArithmeticError),
+    /// `${name:?message}` found `name` unset or null.
+    ParameterNullOrUnset {
+        name: String,
+        message: String,
+    },
+    /// An expansion error in the word at `span`.
+    Expansion {
+        error: Box<ExecutionError>,
+        span: Span,
+    },
+}
+
+impl ExecutionError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ExecutionError::Expansion { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The `$?` a command failing with this error leaves behind: 2 for an
+    /// invalid option and 1 otherwise.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ExecutionError::InvalidOption(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Returns whether the error aborts the command line, and with it a
+    /// non-interactive shell. Expansion errors such as `${x:?}` do, while a
+    /// failed builtin or redirection only fails its command.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ExecutionError::ForkFailed
+                | ExecutionError::Panic
+                | ExecutionError::BadSubstitution(_)
+                | ExecutionError::InvalidCommandSubstitution(_)
+                | ExecutionError::Arithmetic(_)
+                | ExecutionError::ParameterNullOrUnset { .. }
+                | ExecutionError::Expansion { .. }
+        )
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecutionError::ForkFailed => write!(f, "fork failed"),
+            ExecutionError::Panic => write!(f, "internal error"),
+            ExecutionError::InvalidNumberOfArgs => write!(f, "invalid number of arguments"),
+            ExecutionError::NoHomeDirectory => write!(f, "HOME not set"),
+            ExecutionError::InvalidPath => write!(f, "invalid path"),
+            ExecutionError::DirectoryNotFound => write!(f, "no such directory"),
+            ExecutionError::FileError(path, error) => write!(f, "{path}: {error}"),
+            ExecutionError::PipeError(error) => write!(f, "pipe: {error}"),
+            ExecutionError::BadSubstitution(expression) => {
+                write!(f, "{expression}: bad substitution")
+            }
+            ExecutionError::InvalidCommandSubstitution(command) => {
+                write!(f, "$({command}): invalid command substitution")
+            }
+            ExecutionError::ReadonlyVariable(name) => write!(f, "{name}: readonly variable"),
+            ExecutionError::InvalidIdentifier(name) => {
+                write!(f, "`{name}': not a valid identifier")
+            }
+            ExecutionError::BadFileDescriptor(target) => write!(f, "{target}: bad file descriptor"),
+            ExecutionError::AmbiguousRedirect(target) => write!(f, "{target}: ambiguous redirect"),
+            ExecutionError::FileExists(path) => {
+                write!(f, "{path}: cannot overwrite existing file")
+            }
+            ExecutionError::InvalidOption(option) => write!(f, "{option}: invalid option"),
+            ExecutionError::Arithmetic(error) => write!(f, "{error}"),
+            ExecutionError::ParameterNullOrUnset { name, message } => {
+                write!(f, "{name}: {message}")
+            }
+            ExecutionError::Expansion { error, .. } => write!(f, "{error}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    Overflow,
+    InvalidNumber(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    ReadonlyVariable(String),
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArithmeticError::DivisionByZero => write!(f, "division by 0"),
+            ArithmeticError::Overflow => write!(f, "arithmetic overflow"),
+            ArithmeticError::InvalidNumber(number) => write!(f, "{number}: invalid number"),
+            ArithmeticError::UnexpectedToken(token) => {
+                write!(f, "syntax error in expression near `{token}`")
+            }
+            ArithmeticError::UnexpectedEnd => write!(f, "syntax error: expression expected"),
+            ArithmeticError::ReadonlyVariable(name) => write!(f, "{name}: readonly variable"),
+        }
+    }
 }
 
 #[derive(Debug)]
-pub enum ParserError {}
+pub enum ParserError {
+    /// `token`, found at `span`, cannot appear there.
+    UnexpectedToken { token: Token, span: Span },
+    /// The operator `token` at `span` is not followed by a command.
+    MissingCommand { token: Token, span: Span },
+    /// The redirection operator `token` at `span` has no target.
+    MissingRedirectionTarget { token: Token, span: Span },
+}
+
+impl ParserError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::UnexpectedToken { span, .. }
+            | ParserError::MissingCommand { span, .. }
+            | ParserError::MissingRedirectionTarget { span, .. } => *span,
+        }
+    }
+
+    /// Returns whether the input ended before the command was complete, as
+    /// after a trailing `|` or `&&`.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParserError::MissingCommand { .. }
+                | ParserError::UnexpectedToken {
+                    token: Token::EOF,
+                    ..
+                }
+        )
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParserError::UnexpectedToken {
+                token: Token::EOF, ..
+            } => write!(f, "syntax error: unexpected end of input"),
+            ParserError::UnexpectedToken { token, .. } => {
+                write!(f, "syntax error near unexpected token `{token}`")
+            }
+            ParserError::MissingCommand { token, .. } => {
+                write!(f, "syntax error: expected a command after `{token}`")
+            }
+            ParserError::MissingRedirectionTarget { token, .. } => {
+                write!(f, "syntax error: expected a file name after `{token}`")
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum ShellError {
@@ -109,40 +643,91 @@ pub enum ShellError {
     Executor(ExecutionError),
 }
 
+impl ShellError {
+    /// The span of the input the error refers to, if it is known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ShellError::Lexer(error) => Some(error.span()),
+            ShellError::Parser(error) => Some(error.span()),
+            ShellError::Executor(error) => error.span(),
+        }
+    }
+
+    /// Returns whether reading more input could fix the error.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            ShellError::Lexer(error) => error.is_incomplete(),
+            ShellError::Parser(error) => error.is_incomplete(),
+            ShellError::Executor(_) => false,
+        }
+    }
+
+    /// The `$?` a failed command line leaves behind: 2 for syntax errors and
+    /// that of the execution error otherwise.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ShellError::Lexer(_) | ShellError::Parser(_) => 2,
+            ShellError::Executor(error) => error.exit_status(),
+        }
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Lexer(error) => write!(f, "{error}"),
+            ShellError::Parser(error) => write!(f, "{error}"),
+            ShellError::Executor(error) => write!(f, "{error}"),
+        }
+    }
+}
+
 impl From<LexerError> for ShellError {
     fn from(error: LexerError) -> ShellError {
-        return ShellError::Lexer(error);
+        ShellError::Lexer(error)
     }
 }
 
 impl From<ParserError> for ShellError {
     fn from(error: ParserError) -> ShellError {
-        return ShellError::Parser(error);
+        ShellError::Parser(error)
     }
 }
 
 impl From<ExecutionError> for ShellError {
     fn from(error: ExecutionError) -> ShellError {
-        return ShellError::Executor(error);
+        ShellError::Executor(error)
     }
 }
 
-fn builtin_exit(_: SimpleCommand, _: &mut ExecutionContext) -> Result<i32, ExecutionError> {
-    process::exit(0);
+pub type BuiltIn = Box<dyn Fn(&[String], &mut ExecutionContext) -> Result<i32, ExecutionError>>;
+
+pub type BuiltIns = HashMap<String, BuiltIn>;
+
+/// `exit [n]` exits with `n`, or with the status of the last command.
+fn builtin_exit(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    let status = match args.get(1) {
+        Some(status) => status.parse::<i32>().unwrap_or(2) & 0xff,
+        None => context.last_exit_status,
+    };
+    process::exit(status);
 }
 
-fn builtin_cd(cmd: SimpleCommand, _: &mut ExecutionContext) -> Result<i32, ExecutionError> {
-    if cmd.arguments.len() > 2 {
+fn builtin_cd(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    if args.len() > 2 {
         return Err(ExecutionError::InvalidNumberOfArgs);
     }
 
-    let path_str = if cmd.arguments.len() == 1 || cmd.arguments[1] == "~" {
-        match home_dir() {
-            Some(path) => path.to_string_lossy().to_string(),
+    let path_str = if args.len() == 1 {
+        match context
+            .variable("HOME")
+            .or_else(|| home_dir().map(|path| path.to_string_lossy().to_string()))
+        {
+            Some(path) => path,
             None => return Err(ExecutionError::NoHomeDirectory),
         }
     } else {
-        cmd.arguments[1].clone()
+        args[1].clone()
     };
 
     let c_path = match CString::new(path_str) {
@@ -156,10 +741,17 @@ fn builtin_cd(cmd: SimpleCommand, _: &mut ExecutionContext) -> Result<i32, Execu
         return Err(ExecutionError::DirectoryNotFound);
     }
 
+    if let Some(old_pwd) = context.variable("PWD") {
+        context.set_variable("OLDPWD", old_pwd)?;
+    }
+    if let Ok(pwd) = env::current_dir() {
+        context.set_variable("PWD", pwd.to_string_lossy().to_string())?;
+    }
+
     Ok(0)
 }
 
-fn builtin_jobs(_: SimpleCommand, context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+fn builtin_jobs(_: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
     for (idx, pid) in context.jobs.iter().enumerate() {
         if *pid == 0 || *pid == -1 {
             continue;
@@ -169,18 +761,288 @@ fn builtin_jobs(_: SimpleCommand, context: &mut ExecutionContext) -> Result<i32,
     Ok(0)
 }
 
-pub fn built_ins() -> HashMap<
-    String,
-    Box<dyn for<'a> Fn(SimpleCommand, &'a mut ExecutionContext) -> Result<i32, ExecutionError>>,
-> {
-    let mut map: HashMap<
-        String,
-        Box<dyn for<'a> Fn(SimpleCommand, &'a mut ExecutionContext) -> Result<i32, ExecutionError>>,
-    > = HashMap::new();
+/// Whether `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Quotes `value` so that it can be read back by the shell.
+fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Shared implementation of `export` and `readonly`: sets any given values
+/// and then `mark`s each named variable, or lists the variables for which
+/// `is_marked` holds when there are no operands.
+fn mark_variables(
+    args: &[String],
+    context: &mut ExecutionContext,
+    is_marked: fn(&Variable) -> bool,
+    mark: fn(&mut Variable),
+) -> Result<i32, ExecutionError> {
+    let operands: Vec<&String> = args[1..].iter().filter(|arg| *arg != "-p").collect();
+
+    if operands.is_empty() {
+        let mut names: Vec<&String> = context
+            .variables
+            .iter()
+            .filter(|(_, variable)| is_marked(variable))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        for name in names {
+            match &context.variables[name].value {
+                Some(value) => println!("{} {name}={}", args[0], single_quote(value)),
+                None => println!("{} {name}", args[0]),
+            }
+        }
+        return Ok(0);
+    }
+
+    for operand in operands {
+        let (name, value) = match operand.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (operand.as_str(), None),
+        };
+        if !is_valid_name(name) {
+            return Err(ExecutionError::InvalidIdentifier(name.to_string()));
+        }
+        if let Some(value) = value {
+            context.set_variable(name, value.to_string())?;
+        }
+        // A name without a value is still marked, so that it is exported or
+        // readonly once it is assigned.
+        let variable = context
+            .variables
+            .entry(name.to_string())
+            .or_insert(Variable {
+                value: None,
+                exported: false,
+                readonly: false,
+            });
+        mark(variable);
+    }
+
+    Ok(0)
+}
+
+fn builtin_export(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    mark_variables(
+        args,
+        context,
+        |variable| variable.exported,
+        |variable| variable.exported = true,
+    )
+}
+
+fn builtin_readonly(
+    args: &[String],
+    context: &mut ExecutionContext,
+) -> Result<i32, ExecutionError> {
+    mark_variables(
+        args,
+        context,
+        |variable| variable.readonly,
+        |variable| variable.readonly = true,
+    )
+}
+
+fn builtin_unset(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    for name in &args[1..] {
+        // There are no shell functions, so `-f` has nothing to remove.
+        if name == "-v" || name == "-f" {
+            continue;
+        }
+        if !is_valid_name(name) {
+            return Err(ExecutionError::InvalidIdentifier(name.clone()));
+        }
+        context.unset_variable(name)?;
+    }
+    Ok(0)
+}
+
+/// `set [-C|+C] [-o|+o noclobber] [--] [arg...]` turns shell options on (`-`)
+/// or off (`+`) and makes the `arg`s the positional parameters; a bare
+/// `set -o` lists the options.
+fn builtin_set(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    if args.len() == 2 && args[1] == "-o" {
+        let state = if context.noclobber { "on" } else { "off" };
+        println!("noclobber\t{state}");
+        return Ok(0);
+    }
+
+    let mut args = args[1..].iter().peekable();
+    let mut replace_parameters = false;
+    while let Some(arg) =
+        args.next_if(|arg| arg.len() > 1 && (arg.starts_with('-') || arg.starts_with('+')))
+    {
+        if arg == "--" {
+            replace_parameters = true;
+            break;
+        }
+
+        let (sign, flags) = arg.split_at(1);
+        for flag in flags.chars() {
+            let enable = sign == "-";
+            match flag {
+                'C' => context.noclobber = enable,
+                'o' => match args.next().map(String::as_str) {
+                    Some("noclobber") => context.noclobber = enable,
+                    Some(option) => return Err(ExecutionError::InvalidOption(option.to_string())),
+                    None => return Err(ExecutionError::InvalidNumberOfArgs),
+                },
+                _ => return Err(ExecutionError::InvalidOption(format!("{sign}{flag}"))),
+            }
+        }
+    }
+
+    let operands: Vec<String> = args.cloned().collect();
+    if replace_parameters || !operands.is_empty() {
+        context.positional_parameters = operands;
+    }
+    Ok(0)
+}
+
+/// `break [n]` and `continue [n]` leave or restart the `n`th enclosing loop.
+fn loop_control(
+    args: &[String],
+    context: &mut ExecutionContext,
+    control: fn(usize) -> LoopControl,
+) -> Result<i32, ExecutionError> {
+    let levels = match args.get(1) {
+        Some(levels) => match levels.parse::<usize>() {
+            Ok(levels) if levels > 0 => levels,
+            _ => return Err(ExecutionError::InvalidOption(levels.clone())),
+        },
+        None => 1,
+    };
+    if context.loop_depth > 0 {
+        context.loop_control = Some(control(levels.min(context.loop_depth)));
+    }
+    Ok(0)
+}
+
+fn builtin_break(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    loop_control(args, context, LoopControl::Break)
+}
+
+fn builtin_continue(
+    args: &[String],
+    context: &mut ExecutionContext,
+) -> Result<i32, ExecutionError> {
+    loop_control(args, context, LoopControl::Continue)
+}
+
+/// Appends the next line of `fd` to `line`, newline included, reading a byte
+/// at a time so that nothing after the line is consumed. Returns `false` at
+/// end of input.
+pub fn read_line(fd: i32, line: &mut String) -> bool {
+    let mut bytes = Vec::new();
+    let mut byte = 0u8;
+    while unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) } == 1 {
+        bytes.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    !bytes.is_empty()
+}
+
+/// `read [-r] [name...]` reads a line from standard input and splits it into
+/// the named variables with `$IFS`, the last one taking the rest of the line.
+/// Without `-r` a backslash quotes the next character and joins lines.
+fn builtin_read(args: &[String], context: &mut ExecutionContext) -> Result<i32, ExecutionError> {
+    let mut raw = false;
+    let mut names = Vec::new();
+    for arg in &args[1..] {
+        if arg == "-r" {
+            raw = true;
+        } else if is_valid_name(arg) {
+            names.push(arg.as_str());
+        } else {
+            return Err(ExecutionError::InvalidIdentifier(arg.clone()));
+        }
+    }
+    if names.is_empty() {
+        names.push("REPLY");
+    }
+
+    let mut line = String::new();
+    let mut at_end = true;
+    let mut input = String::new();
+    while read_line(libc::STDIN_FILENO, &mut input) {
+        let newline = input.ends_with('\n');
+        if newline {
+            input.pop();
+        }
+        // An unquoted backslash at the end of the line joins the next one.
+        let mut continued = false;
+        let mut chars = input.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' if !raw => match chars.next() {
+                    Some(escaped_char) => line.push(escaped_char),
+                    None => continued = newline,
+                },
+                _ => line.push(ch),
+            }
+        }
+        input.clear();
+        if !continued {
+            at_end = !newline;
+            break;
+        }
+    }
+
+    let ifs = context
+        .variable("IFS")
+        .unwrap_or_else(|| DEFAULT_IFS.to_string());
+    let is_ifs_space = |ch: char| is_ifs_whitespace(ch, &ifs);
+
+    let mut rest = line.trim_matches(is_ifs_space);
+    for (idx, name) in names.iter().enumerate() {
+        let value = if idx == names.len() - 1 {
+            mem::take(&mut rest)
+        } else {
+            let end = rest.find(|ch| ifs.contains(ch)).unwrap_or(rest.len());
+            let field = &rest[..end];
+            rest = rest[end..].trim_start_matches(is_ifs_space);
+            // A single non-blank delimiter also ends the field.
+            if let Some(ch) = rest.chars().next()
+                && ifs.contains(ch)
+            {
+                rest = rest[ch.len_utf8()..].trim_start_matches(is_ifs_space);
+            }
+            field
+        };
+        context.set_variable(name, value.to_string())?;
+    }
+
+    Ok(if at_end { 1 } else { 0 })
+}
+
+/// Builtins whose prefix assignments stay in the shell after they return.
+pub const SPECIAL_BUILT_INS: [&str; 7] = [
+    "break", "continue", "exit", "export", "readonly", "set", "unset",
+];
+
+pub fn built_ins() -> BuiltIns {
+    let mut map: BuiltIns = HashMap::new();
 
     map.insert("exit".to_string(), Box::new(builtin_exit));
     map.insert("cd".to_string(), Box::new(builtin_cd));
     map.insert("jobs".to_string(), Box::new(builtin_jobs));
+    map.insert("export".to_string(), Box::new(builtin_export));
+    map.insert("readonly".to_string(), Box::new(builtin_readonly));
+    map.insert("unset".to_string(), Box::new(builtin_unset));
+    map.insert("set".to_string(), Box::new(builtin_set));
+    map.insert("break".to_string(), Box::new(builtin_break));
+    map.insert("continue".to_string(), Box::new(builtin_continue));
+    map.insert("read".to_string(), Box::new(builtin_read));
 
     map
 }